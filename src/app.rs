@@ -1,108 +1,372 @@
 use std::{
     collections::{HashMap, VecDeque},
-    net::Ipv4Addr,
-    sync::{Arc, Mutex},
-    time::Instant,
+    error::Error,
+    net::IpAddr,
+    cmp::Ordering,
+    sync::mpsc::{Receiver, TryRecvError},
+    time::{Duration, Instant, SystemTime},
 };
 use chrono::{DateTime, Local};
-use crate::constants::{MAX_SAMPLES, TICK_RATE_MS};
+use crate::constants::{
+    DEFAULT_AVG_WINDOWS_SECS, DEFAULT_HISTORY_WINDOW_SECS, DEFAULT_TICK_RATE_MS, FLOW_IDLE_TIMEOUT_SECS, MAX_FLOWS,
+    HISTORY_WINDOW_STEPS_SECS, TICK_RATE_STEPS_MS,
+};
+use crate::conversation::{ConversationDelta, ConversationKey, ConversationTable};
+use crate::flow::{FlowDelta, FlowKey, FlowTable};
+use crate::geo::{AsnDelta, AsnTable, GeoDb};
+use crate::labels::HostLabels;
+use crate::metrics::{self, MetricsSnapshot};
+use crate::netflow::FlowExporter;
+use crate::names::{HostNames, NameObservation};
+use crate::oui::OuiTable;
+use crate::segment::{Segment, SegmentDelta, SegmentTable};
+use crate::services::{Service, ServiceDeltas, ServiceTable};
+use crate::store::{HistoryStore, Kind};
+use pnet::util::MacAddr;
+
+// Bytes a host received and sent since the last tick
+#[derive(Default, Clone, Copy)]
+pub struct HostDelta {
+    pub rx: u64,
+    pub tx: u64,
+}
 
-// From capture thread to UI thread
-pub struct SharedStats {
-    pub traffic_delta: HashMap<Ipv4Addr, u64>,
+// What a capture thread saw since its last hand-off, sent to the UI thread over a channel
+#[derive(Default)]
+pub struct StatsBatch {
+    pub traffic_delta: HashMap<IpAddr, HostDelta>,
+    pub flow_delta: HashMap<FlowKey, FlowDelta>,
+    pub segment_delta: HashMap<Segment, SegmentDelta>,
+    // Source MAC last seen for each tracked IP
+    pub mac_updates: HashMap<IpAddr, MacAddr>,
+    pub name_updates: Vec<NameObservation>,
     pub rx_delta: u64,
     pub tx_delta: u64,
+    // Neither from nor to the capturing host
+    pub fwd_delta: u64,
+    pub packets: u64,
+    // Latest kernel counters, live captures only
+    pub capture: Option<CaptureStats>,
+    // Why the capture thread stopped before the end of its input
+    pub error: Option<String>,
+}
+
+// Kernel capture counters, cumulative since the capture started
+#[derive(Clone, Copy, Default)]
+pub struct CaptureStats {
+    pub received: u64,
+    // No room in the kernel buffer because we did not read fast enough
+    pub dropped: u64,
+    // Dropped by the interface or its driver
+    pub if_dropped: u64,
+}
+
+impl CaptureStats {
+    pub fn lost(&self) -> u64 {
+        self.dropped + self.if_dropped
+    }
+
+    // Share of the packets seen by the kernel that never reached us
+    pub fn loss_ratio(&self) -> f64 {
+        let seen = self.received + self.if_dropped;
+        if seen == 0 { 0.0 } else { self.lost() as f64 / seen as f64 }
+    }
+}
+
+impl StatsBatch {
+    pub fn is_empty(&self) -> bool {
+        self.packets == 0 && self.name_updates.is_empty() && self.capture.is_none() && self.error.is_none()
+    }
+
+    // Fold a later batch into this one
+    pub fn merge(&mut self, other: StatsBatch) {
+        for (ip, delta) in other.traffic_delta {
+            let entry = self.traffic_delta.entry(ip).or_default();
+            entry.rx += delta.rx;
+            entry.tx += delta.tx;
+        }
+        for (key, delta) in other.flow_delta {
+            self.flow_delta.entry(key).and_modify(|d| d.merge(&delta)).or_insert(delta);
+        }
+        for (segment, delta) in other.segment_delta {
+            self.segment_delta.entry(segment).or_default().merge(delta);
+        }
+        self.mac_updates.extend(other.mac_updates);
+        self.name_updates.extend(other.name_updates);
+        self.rx_delta += other.rx_delta;
+        self.tx_delta += other.tx_delta;
+        self.fwd_delta += other.fwd_delta;
+        self.packets += other.packets;
+        self.capture = other.capture.or(self.capture);
+        self.error = other.error.or(self.error.take());
+    }
+}
+
+// Tick rate, graph window and talker averaging windows, all adjustable while running
+#[derive(Clone)]
+pub struct Timing {
+    pub tick_rate: Duration,
+    pub history_window: Duration,
+    pub avg_windows: Vec<Duration>,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self {
+            tick_rate: Duration::from_millis(DEFAULT_TICK_RATE_MS),
+            history_window: Duration::from_secs(DEFAULT_HISTORY_WINDOW_SECS),
+            avg_windows: DEFAULT_AVG_WINDOWS_SECS.iter().map(|&secs| Duration::from_secs(secs)).collect(),
+        }
+    }
+}
+
+impl Timing {
+    // Number of graph points covering the history window
+    pub fn samples(&self) -> usize {
+        (self.history_window.as_millis() / self.tick_rate.as_millis().max(1)).max(1) as usize
+    }
+
+    // Per-host samples must cover both the history window and the longest averaging window
+    fn retention(&self) -> Duration {
+        self.avg_windows.iter().copied().fold(self.history_window, Duration::max)
+    }
+}
+
+// Bytes seen during one tick and how long that tick actually lasted
+#[derive(Clone, Copy)]
+pub struct Sample {
+    pub rx: u64,
+    pub tx: u64,
+    pub duration: Duration,
 }
 
 // Single IP history record
 pub struct IpHistory {
-    pub samples: VecDeque<u64>,
-    pub total_sum: u64,
+    // Per-tick samples over the retention window
+    pub samples: VecDeque<Sample>,
+    pub window_rx: u64,
+    pub window_tx: u64,
+    window_duration: Duration,
     pub peak_rate: f64,
     pub peak_time: DateTime<Local>,
+    pub peak_rx_rate: f64,
+    pub peak_tx_rate: f64,
+    // Lifetime byte counters
+    pub total_rx_bytes: u64,
+    pub total_tx_bytes: u64,
+    pub mac: Option<MacAddr>,
+    pub vendor: Option<&'static str>,
+    // Index into App::interfaces of the interface the host was last seen on
+    pub interface: usize,
+    // First and last tick with traffic, on the App::clock
+    pub first_seen: Option<SystemTime>,
+    pub last_seen: Option<SystemTime>,
+    // Traffic the host sent or received, per application service
+    pub services: ServiceTable,
 }
 
 impl IpHistory {
     pub fn new() -> Self {
         Self {
-            samples: VecDeque::with_capacity(MAX_SAMPLES),
-            total_sum: 0,
+            samples: VecDeque::new(),
+            window_rx: 0,
+            window_tx: 0,
+            window_duration: Duration::ZERO,
             peak_rate: 0.0,
             peak_time: Local::now(),
+            peak_rx_rate: 0.0,
+            peak_tx_rate: 0.0,
+            total_rx_bytes: 0,
+            total_tx_bytes: 0,
+            mac: None,
+            vendor: None,
+            interface: 0,
+            first_seen: None,
+            last_seen: None,
+            services: ServiceTable::default(),
+        }
+    }
+
+    // New history carrying lifetime totals restored from disk
+    pub fn restored(totals: Option<(u64, u64)>) -> Self {
+        let mut history = Self::new();
+        if let Some((rx, tx)) = totals {
+            history.total_rx_bytes = rx;
+            history.total_tx_bytes = tx;
+        }
+        history
+    }
+
+    // (rx, tx) rates during the last tick
+    pub fn current_rates(&self) -> (f64, f64) {
+        match self.samples.back() {
+            Some(sample) if !sample.duration.is_zero() => {
+                let secs = sample.duration.as_secs_f64();
+                (sample.rx as f64 / secs, sample.tx as f64 / secs)
+            }
+            _ => (0.0, 0.0),
+        }
+    }
+
+    // (rx, tx) average rates over the most recent `span`, or over what has been seen so far if shorter
+    pub fn average_over(&self, span: Duration) -> (f64, f64) {
+        let (mut rx, mut tx, mut covered) = (0, 0, Duration::ZERO);
+        for sample in self.samples.iter().rev() {
+            if covered >= span {
+                break;
+            }
+            rx += sample.rx;
+            tx += sample.tx;
+            covered += sample.duration;
+        }
+        if covered.is_zero() {
+            (0.0, 0.0)
+        } else {
+            (rx as f64 / covered.as_secs_f64(), tx as f64 / covered.as_secs_f64())
         }
     }
 
-    pub fn update(&mut self, bytes: u64) -> f64 {
-        let instant_rate = (bytes as f64) * (1000.0 / TICK_RATE_MS as f64);
+    // Add the bytes of a tick that lasted `elapsed`, dropping samples older than `retention`
+    pub fn update(&mut self, delta: HostDelta, elapsed: Duration, retention: Duration) {
+        let to_rate = |bytes: u64| bytes as f64 / elapsed.as_secs_f64();
+        let instant_rate = to_rate(delta.rx + delta.tx);
 
         if instant_rate > self.peak_rate {
             self.peak_rate = instant_rate;
             self.peak_time = Local::now();
         }
+        self.peak_rx_rate = self.peak_rx_rate.max(to_rate(delta.rx));
+        self.peak_tx_rate = self.peak_tx_rate.max(to_rate(delta.tx));
 
-        self.samples.push_back(bytes);
-        self.total_sum += bytes;
-        if self.samples.len() > MAX_SAMPLES {
-            if let Some(removed) = self.samples.pop_front() {
-                self.total_sum -= removed;
+        self.total_rx_bytes += delta.rx;
+        self.total_tx_bytes += delta.tx;
+
+        self.samples.push_back(Sample { rx: delta.rx, tx: delta.tx, duration: elapsed });
+        self.window_rx += delta.rx;
+        self.window_tx += delta.tx;
+        self.window_duration += elapsed;
+        while let Some(oldest) = self.samples.front() {
+            if self.window_duration - oldest.duration < retention {
+                break;
             }
+            self.window_rx -= oldest.rx;
+            self.window_tx -= oldest.tx;
+            self.window_duration -= oldest.duration;
+            self.samples.pop_front();
         }
+    }
+}
 
-        let duration_secs = self.samples.len() as f64 * (TICK_RATE_MS as f64 / 1000.0);
-        if duration_secs == 0.0 {
-            0.0
-        } else {
-            self.total_sum as f64 / duration_secs
-        }
+// One row of the top talkers table
+pub struct Talker {
+    pub ip: IpAddr,
+    pub name: Option<String>,
+    // From the config file
+    pub label: Option<String>,
+    pub group: Option<String>,
+    pub mac: Option<MacAddr>,
+    pub vendor: Option<&'static str>,
+    // Averages over the history window
+    pub avg_rx: f64,
+    pub avg_tx: f64,
+    // Combined rate over each of Timing::avg_windows
+    pub window_rates: Vec<f64>,
+    pub peak_rate: f64,
+    pub peak_time: DateTime<Local>,
+    pub total_rx_bytes: u64,
+    pub total_tx_bytes: u64,
+}
+
+impl Talker {
+    pub fn avg(&self) -> f64 {
+        self.avg_rx + self.avg_tx
     }
 }
 
-// Main application state
-pub struct App {
+// Talkers of one config group added together
+pub struct GroupTotals {
+    pub name: String,
+    pub hosts: usize,
+    pub avg_rx: f64,
+    pub avg_tx: f64,
+    pub window_rates: Vec<f64>,
+    pub total_rx_bytes: u64,
+    pub total_tx_bytes: u64,
+}
+
+// How long the drop warning stays up after the counters last rose
+const DROP_WARNING: Duration = Duration::from_secs(10);
+
+// Row name for talkers that are in no group
+pub const UNGROUPED: &str = "Other";
+
+// RX/TX graph, totals and peaks for one interface (or the aggregate of all of them)
+pub struct InterfaceStats {
+    pub name: String,
+    // Bytes per second of each tick, oldest first
     pub rx_history: Vec<f64>,
     pub tx_history: Vec<f64>,
     pub total_rx_bytes: u64,
     pub total_tx_bytes: u64,
+    // Routed, bridged or mirrored traffic that was neither from nor to this host
+    pub fwd_rate: f64,
+    pub total_fwd_bytes: u64,
     pub peak_rx_record: (f64, DateTime<Local>),
     pub peak_tx_record: (f64, DateTime<Local>),
-    
-    ip_histories: HashMap<Ipv4Addr, IpHistory>,
-    
-    // UI display of top talkers
-    pub top_talkers: Vec<(Ipv4Addr, f64, f64, DateTime<Local>)>,
-    pub last_tick: Instant,
+    pub capture: Option<CaptureStats>,
+    // The capture thread has exited, e.g. at the end of a replayed file
+    pub finished: bool,
+    // The capture thread gave up on a read error instead
+    pub error: Option<String>,
+    // When the drop counters last went up
+    last_drop: Option<Instant>,
 }
 
-impl App {
-    pub fn new() -> App {
+impl InterfaceStats {
+    pub fn new(name: &str, samples: usize) -> Self {
         let now = Local::now();
-        App {
-            rx_history: vec![0.0; MAX_SAMPLES],
-            tx_history: vec![0.0; MAX_SAMPLES],
+        Self {
+            name: name.to_string(),
+            rx_history: vec![0.0; samples],
+            tx_history: vec![0.0; samples],
             total_rx_bytes: 0,
             total_tx_bytes: 0,
+            fwd_rate: 0.0,
+            total_fwd_bytes: 0,
             peak_rx_record: (0.0, now),
             peak_tx_record: (0.0, now),
-            ip_histories: HashMap::new(),
-            top_talkers: vec![],
-            last_tick: Instant::now(),
+            capture: None,
+            finished: false,
+            error: None,
+            last_drop: None,
+        }
+    }
+
+    pub fn update_capture(&mut self, capture: CaptureStats) {
+        if capture.lost() > self.capture.map_or(0, |c| c.lost()) {
+            self.last_drop = Some(Instant::now());
         }
+        self.capture = Some(capture);
     }
 
-    pub fn on_tick(&mut self, shared_stats: &Arc<Mutex<SharedStats>>) {
-        let mut stats = shared_stats.lock().unwrap();
+    // Packets were lost recently, so the rates shown are too low
+    pub fn drops_rising(&self) -> bool {
+        self.last_drop.is_some_and(|t| t.elapsed() < DROP_WARNING)
+    }
+
+    pub fn push(&mut self, rx_delta: u64, tx_delta: u64, fwd_delta: u64, elapsed: Duration) {
+        let current_rx_rate = rx_delta as f64 / elapsed.as_secs_f64();
+        let current_tx_rate = tx_delta as f64 / elapsed.as_secs_f64();
+        self.fwd_rate = fwd_delta as f64 / elapsed.as_secs_f64();
+        self.total_fwd_bytes += fwd_delta;
 
-        // Update overall RX/TX history
         self.rx_history.remove(0);
-        self.rx_history.push(stats.rx_delta as f64);
+        self.rx_history.push(current_rx_rate);
         self.tx_history.remove(0);
-        self.tx_history.push(stats.tx_delta as f64);
-
-        self.total_rx_bytes += stats.rx_delta;
-        self.total_tx_bytes += stats.tx_delta;
+        self.tx_history.push(current_tx_rate);
 
-        let current_rx_rate = (stats.rx_delta as f64) * (1000.0 / TICK_RATE_MS as f64);
-        let current_tx_rate = (stats.tx_delta as f64) * (1000.0 / TICK_RATE_MS as f64);
+        self.total_rx_bytes += rx_delta;
+        self.total_tx_bytes += tx_delta;
 
         if current_rx_rate > self.peak_rx_record.0 {
             self.peak_rx_record = (current_rx_rate, Local::now());
@@ -110,34 +374,649 @@ impl App {
         if current_tx_rate > self.peak_tx_record.0 {
             self.peak_tx_record = (current_tx_rate, Local::now());
         }
+    }
+
+    // Keep the most recent points when the window shrinks, pad with zeros on the left when it grows
+    pub fn resize(&mut self, samples: usize) {
+        for history in [&mut self.rx_history, &mut self.tx_history] {
+            if history.len() > samples {
+                history.drain(..history.len() - samples);
+            } else {
+                history.splice(0..0, std::iter::repeat_n(0.0, samples - history.len()));
+            }
+        }
+    }
+
+    pub fn current_rx_rate(&self) -> f64 {
+        *self.rx_history.last().unwrap_or(&0.0)
+    }
+
+    pub fn current_tx_rate(&self) -> f64 {
+        *self.tx_history.last().unwrap_or(&0.0)
+    }
+}
+
+// Which table fills the middle of the screen
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum View {
+    Talkers,
+    Flows,
+    Groups,
+    Segments,
+    Services,
+    Conversations,
+    Destinations,
+}
+
+impl std::str::FromStr for View {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "talkers" => Ok(View::Talkers),
+            "flows" => Ok(View::Flows),
+            "groups" => Ok(View::Groups),
+            "segments" => Ok(View::Segments),
+            "services" => Ok(View::Services),
+            "conversations" => Ok(View::Conversations),
+            "destinations" => Ok(View::Destinations),
+            _ => Err(format!("unknown view '{}', expected talkers, flows, groups, segments, services, conversations or destinations", s)),
+        }
+    }
+}
+
+// Column the talkers table is ordered by
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SortColumn {
+    Ip,
+    Avg,
+    Rx,
+    Tx,
+    Peak,
+    PeakTime,
+    Total,
+}
+
+impl SortColumn {
+    const ALL: [SortColumn; 7] = [
+        SortColumn::Avg,
+        SortColumn::Rx,
+        SortColumn::Tx,
+        SortColumn::Peak,
+        SortColumn::PeakTime,
+        SortColumn::Total,
+        SortColumn::Ip,
+    ];
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&c| c == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SortColumn::Ip => "IP",
+            SortColumn::Avg => "average",
+            SortColumn::Rx => "RX",
+            SortColumn::Tx => "TX",
+            SortColumn::Peak => "peak rate",
+            SortColumn::PeakTime => "peak time",
+            SortColumn::Total => "total",
+        }
+    }
+
+    // Natural order: addresses ascending, everything else biggest or most recent first
+    fn compare(self, a: &Talker, b: &Talker) -> Ordering {
+        match self {
+            SortColumn::Ip => a.ip.cmp(&b.ip),
+            SortColumn::Avg => b.avg().total_cmp(&a.avg()),
+            SortColumn::Rx => b.avg_rx.total_cmp(&a.avg_rx),
+            SortColumn::Tx => b.avg_tx.total_cmp(&a.avg_tx),
+            SortColumn::Peak => b.peak_rate.total_cmp(&a.peak_rate),
+            SortColumn::PeakTime => b.peak_time.cmp(&a.peak_time),
+            SortColumn::Total => (b.total_rx_bytes + b.total_tx_bytes).cmp(&(a.total_rx_bytes + a.total_tx_bytes)),
+        }
+    }
+}
+
+// Main application state
+pub struct App {
+    // Sum over every capture source
+    pub aggregate: InterfaceStats,
+    // One entry per capture source, in the same order as the stats passed to on_tick
+    pub interfaces: Vec<InterfaceStats>,
+    // Interface shown in the graph box, None for the aggregate view
+    pub selected_interface: Option<usize>,
+    
+    ip_histories: HashMap<IpAddr, IpHistory>,
+    oui: OuiTable,
+    // Names learned passively, kept even after a host goes quiet
+    pub hostnames: HashMap<IpAddr, HostNames>,
+    
+    // Labels and groups from the config file
+    pub labels: HostLabels,
+    // UI display of top talkers
+    pub top_talkers: Vec<Talker>,
+    // Row limit from the config file, unlimited when None
+    pub talker_rows: Option<usize>,
+    // Talkers table ordering and the selected host, kept across ticks
+    pub sort_column: SortColumn,
+    pub sort_reversed: bool,
+    pub selected_talker: Option<IpAddr>,
+    // Host whose full-screen detail pane is open
+    pub detail_host: Option<IpAddr>,
+    pub flows: FlowTable,
+    // Traffic per VLAN and per VXLAN/GENEVE network
+    pub segments: SegmentTable,
+    // Traffic per IP protocol and well-known service port
+    pub services: ServiceTable,
+    // Who talks to whom: tracked host pairs and tracked host <-> external address pairs
+    pub conversations: ConversationTable,
+    // Country and ASN databases for external addresses, empty unless --geoip is given
+    pub geo: GeoDb,
+    // Traffic between tracked hosts and each autonomous system, filled only with a database
+    pub asns: AsnTable,
+    pub view: View,
+    pub timing: Timing,
+    // Prometheus exposition text, re-rendered every tick when the exporter is enabled
+    pub metrics: Option<MetricsSnapshot>,
+    // NetFlow/IPFIX probe fed with the same flow deltas as the flow table
+    pub flow_exporter: Option<FlowExporter>,
+    // On-disk history, and the lifetime host totals it restored that have not been seen yet
+    history: Option<HistoryStore>,
+    restored_totals: HashMap<IpAddr, (u64, u64)>,
+    // Last failed history write, cleared once a write succeeds again
+    pub history_error: Option<String>,
+    // Replaying a capture file: packet timestamps drive the clock instead of the wall clock
+    pub offline: bool,
+    pub last_tick: Instant,
+}
+
+impl App {
+    pub fn new(interface_names: &[String], timing: Timing) -> App {
+        let samples = timing.samples();
+        App {
+            aggregate: InterfaceStats::new("all", samples),
+            interfaces: interface_names.iter().map(|name| InterfaceStats::new(name, samples)).collect(),
+            selected_interface: None,
+            ip_histories: HashMap::new(),
+            oui: OuiTable::bundled(),
+            hostnames: HashMap::new(),
+            labels: HostLabels::default(),
+            top_talkers: vec![],
+            talker_rows: None,
+            sort_column: SortColumn::Avg,
+            sort_reversed: false,
+            selected_talker: None,
+            detail_host: None,
+            flows: FlowTable::new(Duration::from_secs(FLOW_IDLE_TIMEOUT_SECS), MAX_FLOWS),
+            segments: SegmentTable::default(),
+            services: ServiceTable::default(),
+            conversations: ConversationTable::new(Duration::from_secs(FLOW_IDLE_TIMEOUT_SECS), MAX_FLOWS),
+            geo: GeoDb::default(),
+            asns: AsnTable::default(),
+            view: View::Talkers,
+            timing,
+            metrics: None,
+            flow_exporter: None,
+            history: None,
+            restored_totals: HashMap::new(),
+            history_error: None,
+            offline: false,
+            last_tick: Instant::now(),
+        }
+    }
+
+    // Cycle the graph box through aggregate -> each interface -> aggregate
+    pub fn next_interface(&mut self) {
+        self.selected_interface = match self.selected_interface {
+            None if !self.interfaces.is_empty() => Some(0),
+            Some(i) if i + 1 < self.interfaces.len() => Some(i + 1),
+            _ => None,
+        };
+    }
+
+    pub fn displayed_interface(&self) -> &InterfaceStats {
+        match self.selected_interface {
+            Some(i) => &self.interfaces[i],
+            None => &self.aggregate,
+        }
+    }
+
+    // Talkers shown in the table, in table order
+    pub fn visible_talkers(&self) -> &[Talker] {
+        let rows = self.talker_rows.unwrap_or(usize::MAX).min(self.top_talkers.len());
+        &self.top_talkers[..rows]
+    }
+
+    pub fn cycle_sort(&mut self) {
+        self.sort_column = self.sort_column.next();
+        self.sort_talkers();
+    }
+
+    pub fn reverse_sort(&mut self) {
+        self.sort_reversed = !self.sort_reversed;
+        self.sort_talkers();
+    }
+
+    fn sort_talkers(&mut self) {
+        let (column, reversed) = (self.sort_column, self.sort_reversed);
+        // Ties fall back to the address so rows do not jump around between ticks
+        self.top_talkers.sort_by(|a, b| {
+            let order = column.compare(a, b).then_with(|| a.ip.cmp(&b.ip));
+            if reversed { order.reverse() } else { order }
+        });
+    }
+
+    // Index of the selected host in the table, if it is still listed
+    pub fn selected_index(&self) -> Option<usize> {
+        let ip = self.selected_talker?;
+        self.visible_talkers().iter().position(|talker| talker.ip == ip)
+    }
+
+    // Move the selection by `delta` rows, starting from the top when nothing is selected
+    pub fn move_selection(&mut self, delta: isize) {
+        let talkers = self.visible_talkers();
+        if talkers.is_empty() {
+            return;
+        }
+        let index = match self.selected_index() {
+            Some(index) => index.saturating_add_signed(delta).min(talkers.len() - 1),
+            None => 0,
+        };
+        self.selected_talker = Some(talkers[index].ip);
+    }
+
+    pub fn select_first(&mut self) {
+        self.selected_talker = self.visible_talkers().first().map(|talker| talker.ip);
+    }
+
+    pub fn select_last(&mut self) {
+        self.selected_talker = self.visible_talkers().last().map(|talker| talker.ip);
+    }
+
+    // Open the detail pane of the selected host, or close the one that is open
+    pub fn toggle_detail(&mut self) {
+        self.detail_host = match self.detail_host {
+            Some(_) => None,
+            None => self.selected_talker,
+        };
+    }
+
+    pub fn toggle_view(&mut self, view: View) {
+        self.view = if self.view == view { View::Talkers } else { view };
+    }
+
+    // Move the tick interval to the next longer (or shorter) step
+    pub fn step_tick_rate(&mut self, longer: bool) {
+        let current = self.timing.tick_rate.as_millis() as u64;
+        self.set_tick_rate(Duration::from_millis(next_step(&TICK_RATE_STEPS_MS, current, longer)));
+    }
+
+    // Move the history window to the next wider (or narrower) step
+    pub fn step_history_window(&mut self, wider: bool) {
+        let current = self.timing.history_window.as_secs();
+        self.set_history_window(Duration::from_secs(next_step(&HISTORY_WINDOW_STEPS_SECS, current, wider)));
+    }
+
+    pub fn set_tick_rate(&mut self, tick_rate: Duration) {
+        self.timing.tick_rate = tick_rate;
+        self.resize_graphs();
+    }
+
+    pub fn set_history_window(&mut self, window: Duration) {
+        self.timing.history_window = window;
+        self.resize_graphs();
+    }
+
+    fn resize_graphs(&mut self) {
+        let samples = self.timing.samples();
+        self.aggregate.resize(samples);
+        for iface in &mut self.interfaces {
+            iface.resize(samples);
+        }
+    }
+
+    // Current time as seen by the traffic: wall clock when live, last packet timestamp when replaying
+    pub fn clock(&self) -> SystemTime {
+        if self.offline {
+            self.flows.latest_seen().unwrap_or_else(SystemTime::now)
+        } else {
+            SystemTime::now()
+        }
+    }
+
+    // Talkers summed per group, busiest first
+    pub fn group_totals(&self) -> Vec<GroupTotals> {
+        let mut groups: Vec<GroupTotals> = Vec::new();
+        for talker in &self.top_talkers {
+            let name = talker.group.as_deref().unwrap_or(UNGROUPED);
+            let index = match groups.iter().position(|g| g.name == name) {
+                Some(index) => index,
+                None => {
+                    groups.push(GroupTotals {
+                        name: name.to_string(),
+                        hosts: 0,
+                        avg_rx: 0.0,
+                        avg_tx: 0.0,
+                        window_rates: vec![0.0; talker.window_rates.len()],
+                        total_rx_bytes: 0,
+                        total_tx_bytes: 0,
+                    });
+                    groups.len() - 1
+                }
+            };
+            let group = &mut groups[index];
+            group.hosts += 1;
+            group.avg_rx += talker.avg_rx;
+            group.avg_tx += talker.avg_tx;
+            for (sum, rate) in group.window_rates.iter_mut().zip(&talker.window_rates) {
+                *sum += rate;
+            }
+            group.total_rx_bytes += talker.total_rx_bytes;
+            group.total_tx_bytes += talker.total_tx_bytes;
+        }
+        groups.sort_by(|a, b| (b.avg_rx + b.avg_tx).total_cmp(&(a.avg_rx + a.avg_tx)));
+        groups
+    }
+
+    pub fn hosts(&self) -> impl Iterator<Item = (&IpAddr, &IpHistory)> {
+        self.ip_histories.iter()
+    }
+
+    pub fn host(&self, ip: &IpAddr) -> Option<&IpHistory> {
+        self.ip_histories.get(ip)
+    }
+
+    // Persist counters to `store` and restore the lifetime totals it already holds
+    pub fn attach_history(&mut self, store: HistoryStore) -> Result<(), Box<dyn Error>> {
+        let interface_totals = store.load_totals(Kind::Interface)?;
+        for iface in &mut self.interfaces {
+            if let Some(&(rx, tx)) = interface_totals.get(&iface.name) {
+                iface.total_rx_bytes = rx;
+                iface.total_tx_bytes = tx;
+                self.aggregate.total_rx_bytes += rx;
+                self.aggregate.total_tx_bytes += tx;
+            }
+        }
+        self.restored_totals = store.load_totals(Kind::Host)?
+            .into_iter()
+            .filter_map(|(key, totals)| key.parse().ok().map(|ip| (ip, totals)))
+            .collect();
+        self.history = Some(store);
+        Ok(())
+    }
+
+    // Called once when the program exits
+    pub fn shutdown(&mut self) {
+        let now = self.clock();
+        if let Some(exporter) = &mut self.flow_exporter {
+            let _ = exporter.flush(now);
+        }
+        if let Some(store) = &mut self.history {
+            self.history_error = store.flush(now).err().map(|err| err.to_string());
+        }
+    }
+
+    // The flow table hit its size limit recently, so the flow view and exports miss some flows
+    pub fn flows_evicting(&self) -> bool {
+        self.flows.last_eviction().is_some_and(|t| t.elapsed() < DROP_WARNING)
+    }
+
+    // Same for the conversation table
+    pub fn conversations_evicting(&self) -> bool {
+        self.conversations.last_eviction().is_some_and(|t| t.elapsed() < DROP_WARNING)
+    }
+
+    // Every capture thread has exited, so no more traffic will arrive
+    pub fn finished(&self) -> bool {
+        self.interfaces.iter().all(|iface| iface.finished)
+    }
+
+    pub fn on_tick(&mut self, receivers: &[Receiver<StatsBatch>]) {
+        // Rates use how long the tick really lasted, which drifts from the tick rate under load or after a change
+        let elapsed = self.last_tick.elapsed().max(Duration::from_millis(1));
+        self.last_tick = Instant::now();
+        // Keep the figures of a finished replay on screen instead of letting the rates decay to zero
+        if self.finished() {
+            return;
+        }
+
+        let mut rx_sum = 0;
+        let mut tx_sum = 0;
+        let mut fwd_sum = 0;
+        let mut traffic_delta: HashMap<IpAddr, HostDelta> = HashMap::new();
+        let mut flow_delta: HashMap<FlowKey, FlowDelta> = HashMap::new();
+        let mut segment_delta: HashMap<Segment, SegmentDelta> = HashMap::new();
+        let mut mac_updates: HashMap<IpAddr, MacAddr> = HashMap::new();
+        let mut seen_on: HashMap<IpAddr, usize> = HashMap::new();
+
+        for (index, (iface, receiver)) in self.interfaces.iter_mut().zip(receivers).enumerate() {
+            // Everything the capture thread handed off since the last tick; it never waits for us
+            let mut stats = StatsBatch::default();
+            loop {
+                match receiver.try_recv() {
+                    Ok(batch) => stats.merge(batch),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        iface.finished = true;
+                        break;
+                    }
+                }
+            }
+
+            // Update per-interface RX/TX history
+            iface.push(stats.rx_delta, stats.tx_delta, stats.fwd_delta, elapsed);
+            if let Some(store) = &mut self.history {
+                store.record(Kind::Interface, &iface.name, stats.rx_delta, stats.tx_delta);
+            }
+            rx_sum += stats.rx_delta;
+            tx_sum += stats.tx_delta;
+            fwd_sum += stats.fwd_delta;
+            if let Some(capture) = stats.capture {
+                iface.update_capture(capture);
+            }
+            if let Some(error) = stats.error.take() {
+                iface.error = Some(error);
+            }
+
+            for (ip, delta) in stats.traffic_delta.drain() {
+                seen_on.insert(ip, index);
+                let entry = traffic_delta.entry(ip).or_default();
+                entry.rx += delta.rx;
+                entry.tx += delta.tx;
+            }
+            for (key, delta) in stats.flow_delta.drain() {
+                flow_delta.entry(key).and_modify(|d| d.merge(&delta)).or_insert(delta);
+            }
+            for (segment, delta) in stats.segment_delta.drain() {
+                segment_delta.entry(segment).or_default().merge(delta);
+            }
+            mac_updates.extend(stats.mac_updates.drain());
+            for observed in stats.name_updates.drain(..) {
+                let names = self.hostnames.entry(observed.ip).or_default();
+                names.insert(observed.source, observed.name);
+                if let (Some(store), Some(best)) = (&mut self.history, names.best()) {
+                    store.record_name(&observed.ip.to_string(), best);
+                }
+            }
+        }
+
+        // Update overall RX/TX history
+        self.aggregate.push(rx_sum, tx_sum, fwd_sum, elapsed);
+        let captures: Vec<CaptureStats> = self.interfaces.iter().filter_map(|iface| iface.capture).collect();
+        if !captures.is_empty() {
+            self.aggregate.update_capture(CaptureStats {
+                received: captures.iter().map(|c| c.received).sum(),
+                dropped: captures.iter().map(|c| c.dropped).sum(),
+                if_dropped: captures.iter().map(|c| c.if_dropped).sum(),
+            });
+        }
+
+        // Update the flow table
+        self.flows.merge(&flow_delta, elapsed.as_secs_f64());
+        self.segments.merge(segment_delta, elapsed.as_secs_f64());
+
+        // Split the flow deltas per service, for the whole network and for each tracked end
+        let mut service_delta = ServiceDeltas::new();
+        let mut host_service_delta: HashMap<IpAddr, ServiceDeltas> = HashMap::new();
+        for (key, delta) in &flow_delta {
+            let service = Service::of(key);
+            let add = |deltas: &mut ServiceDeltas| {
+                let entry = deltas.entry(service).or_default();
+                entry.0 += delta.bytes;
+                entry.1 += delta.packets;
+            };
+            add(&mut service_delta);
+            for ip in [key.src, key.dst] {
+                if traffic_delta.contains_key(&ip) {
+                    add(host_service_delta.entry(ip).or_default());
+                }
+            }
+        }
+        self.services.merge(&service_delta, elapsed.as_secs_f64());
+
+        // Pair up the two ends of every flow that touches a tracked host
+        let mut conversation_delta: HashMap<ConversationKey, ConversationDelta> = HashMap::new();
+        for (key, delta) in &flow_delta {
+            let (src_tracked, dst_tracked) = (traffic_delta.contains_key(&key.src), traffic_delta.contains_key(&key.dst));
+            if !src_tracked && !dst_tracked {
+                continue;
+            }
+            let forward = if src_tracked != dst_tracked { src_tracked } else { key.src <= key.dst };
+            let pair = if forward {
+                ConversationKey { a: key.src, b: key.dst }
+            } else {
+                ConversationKey { a: key.dst, b: key.src }
+            };
+            let conversation = conversation_delta.entry(pair)
+                .or_insert_with(|| ConversationDelta::new(delta.last_seen, src_tracked && dst_tracked));
+            if forward {
+                conversation.a_to_b += delta.bytes;
+            } else {
+                conversation.b_to_a += delta.bytes;
+            }
+            conversation.packets += delta.packets;
+            conversation.last_seen = conversation.last_seen.max(delta.last_seen);
+        }
+        self.conversations.merge(&conversation_delta, elapsed.as_secs_f64(), &self.geo);
+
+        // Attribute external conversations to the autonomous system of the remote end
+        if !self.geo.is_empty() {
+            let mut asn_delta: HashMap<u32, AsnDelta> = HashMap::new();
+            for (key, delta) in conversation_delta.iter().filter(|(_, delta)| !delta.local) {
+                let info = self.conversations.get(key).and_then(|c| c.remote.clone()).unwrap_or_default();
+                let asn = asn_delta.entry(info.asn.unwrap_or(0)).or_default();
+                asn.sent += delta.a_to_b;
+                asn.received += delta.b_to_a;
+                asn.hosts.insert(key.a);
+                if asn.info.asn.is_none() {
+                    asn.info = info;
+                }
+            }
+            self.asns.merge(asn_delta, elapsed.as_secs_f64());
+        }
+        let now = self.clock();
+        self.conversations.expire(now);
+        self.flows.expire(now);
+        if let Some(exporter) = &mut self.flow_exporter {
+            exporter.observe(&flow_delta);
+            // An unreachable collector must not interrupt monitoring; unsent records stay cached and are retried next tick
+            let _ = exporter.expire(now);
+        }
+
+        // Record MAC addresses and resolve their vendors
+        for (ip, mac) in mac_updates {
+            let history = self.ip_histories.entry(ip).or_insert_with(|| IpHistory::restored(self.restored_totals.remove(&ip)));
+            if history.mac != Some(mac) {
+                history.mac = Some(mac);
+                history.vendor = self.oui.lookup(mac);
+            }
+        }
 
         // Update per-IP histories and top talkers
-        let mut all_ips: Vec<Ipv4Addr> = self.ip_histories.keys().cloned().collect();
-        for k in stats.traffic_delta.keys() {
+        let mut all_ips: Vec<IpAddr> = self.ip_histories.keys().cloned().collect();
+        for k in traffic_delta.keys() {
             if !self.ip_histories.contains_key(k) {
                 all_ips.push(*k);
             }
         }
 
+        let retention = self.timing.retention();
         let mut current_snapshot = Vec::new();
         for ip in all_ips {
-            let bytes_in = *stats.traffic_delta.get(&ip).unwrap_or(&0);
-            let history = self.ip_histories.entry(ip).or_insert_with(IpHistory::new);
+            let delta = traffic_delta.get(&ip).copied().unwrap_or_default();
+            let history = self.ip_histories.entry(ip).or_insert_with(|| IpHistory::restored(self.restored_totals.remove(&ip)));
+            if let (Some(store), true) = (&mut self.history, delta.rx + delta.tx > 0) {
+                store.record(Kind::Host, &ip.to_string(), delta.rx, delta.tx);
+            }
+            if let Some(&index) = seen_on.get(&ip) {
+                history.interface = index;
+            }
 
-            let avg_bps = history.update(bytes_in);
+            history.update(delta, elapsed, retention);
+            history.services.merge(&host_service_delta.remove(&ip).unwrap_or_default(), elapsed.as_secs_f64());
+            if delta.rx + delta.tx > 0 {
+                history.first_seen.get_or_insert(now);
+                history.last_seen = Some(now);
+            }
 
-            if history.total_sum > 0 || history.peak_rate > 0.0 {
-                current_snapshot.push((ip, avg_bps, history.peak_rate, history.peak_time));
+            if history.window_rx + history.window_tx > 0 || history.peak_rate > 0.0 {
+                let (avg_rx, avg_tx) = history.average_over(self.timing.history_window);
+                let window_rates = self.timing.avg_windows.iter().map(|&span| {
+                    let (rx, tx) = history.average_over(span);
+                    rx + tx
+                }).collect();
+                current_snapshot.push(Talker {
+                    ip,
+                    name: self.hostnames.get(&ip).and_then(|names| names.best()).map(String::from),
+                    label: self.labels.label(&ip, history.mac).map(String::from),
+                    group: self.labels.group(&ip, history.mac).map(String::from),
+                    mac: history.mac,
+                    vendor: history.vendor,
+                    avg_rx,
+                    avg_tx,
+                    window_rates,
+                    peak_rate: history.peak_rate,
+                    peak_time: history.peak_time,
+                    total_rx_bytes: history.total_rx_bytes,
+                    total_tx_bytes: history.total_tx_bytes,
+                });
             } else {
                 self.ip_histories.remove(&ip);
             }
         }
 
-        current_snapshot.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
         self.top_talkers = current_snapshot;
+        self.sort_talkers();
+
+        // Names of tracked hosts and of conversation peers stay; other addresses only until they go stale
+        let peers = self.conversations.addresses();
+        self.hostnames.retain(|ip, names| {
+            self.ip_histories.contains_key(ip) || peers.contains(ip) || !names.is_stale(Duration::from_secs(FLOW_IDLE_TIMEOUT_SECS))
+        });
+
+        if let Some(store) = &mut self.history {
+            // A failing disk must not interrupt monitoring: the counters stay pending and the error is shown
+            match store.maybe_flush(now) {
+                Ok(true) => self.history_error = None,
+                Ok(false) => {}
+                Err(err) => self.history_error = Some(err.to_string()),
+            }
+        }
+
+        if let Some(metrics) = &self.metrics {
+            let rendered = metrics::render(self);
+            *metrics.lock().unwrap() = rendered;
+        }
+    }
+}
 
-        stats.traffic_delta.clear();
-        stats.rx_delta = 0;
-        stats.tx_delta = 0;
+// Next value of `steps` above (or below) `current`, staying at the ends
+fn next_step(steps: &[u64], current: u64, up: bool) -> u64 {
+    if up {
+        steps.iter().copied().find(|&s| s > current).unwrap_or(current)
+    } else {
+        steps.iter().rev().copied().find(|&s| s < current).unwrap_or(current)
     }
 }
@@ -0,0 +1,111 @@
+use std::{
+    error::Error,
+    hint::black_box,
+    mem,
+    net::Ipv4Addr,
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use pcap::Linktype;
+
+use crate::app::{App, StatsBatch, Timing};
+use crate::network::{self, Batcher, CaptureContext, LocalAddrs, TrackFilter};
+
+// UDP payload size of the synthetic packets
+const PAYLOAD_LEN: usize = 64;
+// Source ports used per host, so the flow table sees realistic churn
+const PORTS_PER_HOST: u16 = 64;
+
+// Ethernet + IPv4 + UDP frame from a tracked LAN host to an internet address
+fn frame(host: u32, src_port: u16) -> Vec<u8> {
+    let src = Ipv4Addr::from(u32::from(Ipv4Addr::new(192, 168, 0, 0)) + host);
+    let dst = Ipv4Addr::new(198, 51, 100, (host % 250) as u8 + 1);
+    let ip_len = (20 + 8 + PAYLOAD_LEN) as u16;
+
+    let mut data = Vec::with_capacity(14 + ip_len as usize);
+    data.extend_from_slice(&[0x02, 0, 0, 0, 0, 1]);
+    data.extend_from_slice(&[0x02, 0, 0, (host >> 16) as u8, (host >> 8) as u8, host as u8]);
+    data.extend_from_slice(&0x0800u16.to_be_bytes());
+    data.extend_from_slice(&[0x45, 0]);
+    data.extend_from_slice(&ip_len.to_be_bytes());
+    data.extend_from_slice(&[0, 0, 0, 0, 64, 17, 0, 0]);
+    data.extend_from_slice(&src.octets());
+    data.extend_from_slice(&dst.octets());
+    data.extend_from_slice(&src_port.to_be_bytes());
+    data.extend_from_slice(&443u16.to_be_bytes());
+    data.extend_from_slice(&((8 + PAYLOAD_LEN) as u16).to_be_bytes());
+    data.extend_from_slice(&[0, 0]);
+    data.resize(data.len() + PAYLOAD_LEN, 0);
+    data
+}
+
+fn rate(packets: u64, elapsed: Duration) -> String {
+    format!("{:>12.0} packets/s ({:.2}s)", packets as f64 / elapsed.as_secs_f64(), elapsed.as_secs_f64())
+}
+
+// Push synthetic packets through the same code as a capture thread and report the packets-per-second ceiling
+pub fn run(packets: u64, hosts: u32) -> Result<(), Box<dyn Error>> {
+    if hosts == 0 || hosts > 65_000 {
+        return Err("--hosts must be between 1 and 65000".into());
+    }
+    let frames: Vec<Vec<u8>> = (0..hosts)
+        .flat_map(|host| (0..PORTS_PER_HOST).map(move |port| frame(host + 1, 40_000 + port)))
+        .collect();
+    let ctx = CaptureContext { local: LocalAddrs::default(), track: TrackFilter::default() };
+    println!("{} packets of {} bytes from {} hosts over {} flows", packets, frames[0].len(), hosts, frames.len());
+
+    // Parsing and accumulation alone, on one thread
+    let started = Instant::now();
+    let mut batch = StatsBatch::default();
+    for (i, data) in frames.iter().cycle().take(packets as usize).enumerate() {
+        network::process_packet(data, data.len() as u64, SystemTime::now(), Linktype::ETHERNET, &ctx, &mut batch);
+        if i % 10_000 == 0 {
+            black_box(mem::take(&mut batch));
+        }
+    }
+    println!("parse only:      {}", rate(packets, started.elapsed()));
+
+    // Capture thread batching into the channel while the UI thread runs on_tick
+    let (sender, receiver) = network::stats_channel();
+    let producer_frames = frames.clone();
+    let started = Instant::now();
+    let producer = thread::spawn(move || {
+        let mut batcher = Batcher::new(sender);
+        for data in producer_frames.iter().cycle().take(packets as usize) {
+            network::process_packet(data, data.len() as u64, SystemTime::now(), Linktype::ETHERNET, &ctx, batcher.batch());
+            batcher.maybe_send();
+        }
+        batcher.finish();
+        started.elapsed()
+    });
+
+    let timing = Timing { tick_rate: Duration::from_millis(100), ..Timing::default() };
+    let tick_rate = timing.tick_rate;
+    let mut app = App::new(&["bench".to_string()], timing);
+    let receivers = [receiver];
+    let expected_bytes = frames.iter().cycle().take(packets as usize).map(|f| f.len() as u64).sum::<u64>();
+    let (mut ticks, mut tick_time, mut slowest_tick) = (0u32, Duration::ZERO, Duration::ZERO);
+    while app.aggregate.total_rx_bytes < expected_bytes {
+        thread::sleep(tick_rate.saturating_sub(app.last_tick.elapsed()));
+        let tick_started = Instant::now();
+        app.on_tick(&receivers);
+        let took = tick_started.elapsed();
+        ticks += 1;
+        tick_time += took;
+        slowest_tick = slowest_tick.max(took);
+    }
+    let capture_elapsed = producer.join().map_err(|_| "capture thread panicked")?;
+
+    println!("capture thread:  {}", rate(packets, capture_elapsed));
+    println!("end to end:      {}", rate(packets, started.elapsed()));
+    println!(
+        "on_tick:         {} ticks, {:.2} ms average, {:.2} ms slowest, {} hosts, {} flows",
+        ticks,
+        tick_time.as_secs_f64() * 1000.0 / ticks.max(1) as f64,
+        slowest_tick.as_secs_f64() * 1000.0,
+        app.top_talkers.len(),
+        app.flows.len()
+    );
+    Ok(())
+}
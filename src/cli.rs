@@ -0,0 +1,183 @@
+use std::{error::Error, net::SocketAddr, path::PathBuf, str::FromStr, time::Duration};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use pnet::ipnetwork::IpNetwork;
+
+use crate::config::Config;
+use crate::netflow::FlowVersion;
+use crate::network::PlaybackSpeed;
+use crate::util::parse_duration;
+
+#[derive(Parser)]
+#[command(name = "net_monitor", version, about = "Per-host bandwidth monitor for the local network")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub monitor: MonitorArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    #[command(short_flag = 'l', long_flag = "list-interfaces", about = "List the interfaces that can be captured on (* marks the default)")]
+    ListInterfaces,
+
+    #[command(long_flag = "report", about = "Print the top hosts by traffic over PERIOD from the --db history")]
+    Report {
+        #[arg(value_parser = parse_duration, help = "Period to report on, such as 90m, 24h or 7d")]
+        period: Duration,
+    },
+
+    #[command(about = "Measure how many packets per second the capture-to-UI pipeline sustains, using synthetic traffic")]
+    Bench {
+        #[arg(long, default_value_t = 5_000_000, help = "Number of packets to process")]
+        packets: u64,
+
+        #[arg(long, default_value_t = 254, help = "Number of distinct LAN hosts sending them")]
+        hosts: u32,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputMode {
+    Tui,
+    Json,
+    Csv,
+}
+
+#[derive(Args)]
+pub struct MonitorArgs {
+    #[arg(value_name = "CIDR", help = "Only track addresses inside these networks, same as --include")]
+    pub cidrs: Vec<IpNetwork>,
+
+    #[arg(long, value_name = "CIDR", value_delimiter = ',', help = "Only track addresses inside these networks (default: RFC1918, IPv6 link-local and ULA)")]
+    pub include: Vec<IpNetwork>,
+
+    #[arg(long, value_name = "CIDR", value_delimiter = ',', help = "Never track addresses inside these networks")]
+    pub exclude: Vec<IpNetwork>,
+
+    #[arg(short, long = "interface", value_name = "IFACE", value_delimiter = ',', help = "Interfaces to capture on (default: the system default)")]
+    pub interfaces: Vec<String>,
+
+    #[arg(short, long, value_name = "BPF", help = "Kernel capture filter, e.g. \"not port 22\" [default: generated from the tracked networks]")]
+    pub filter: Option<String>,
+
+    #[arg(long, conflicts_with = "filter", help = "Capture every packet instead of a filter generated from the tracked networks")]
+    pub no_auto_filter: bool,
+
+    #[arg(short, long, value_name = "FILE", conflicts_with = "interfaces", help = "Replay a pcap/pcapng file instead of capturing live")]
+    pub read: Option<PathBuf>,
+
+    #[arg(long, requires = "read", help = "Replay speed: realtime, max or a factor such as 10x [default: realtime]")]
+    pub speed: Option<PlaybackSpeed>,
+
+    #[arg(long, value_name = "MS", value_parser = clap::value_parser!(u64).range(1..), help = "Statistics tick in milliseconds [default: 500]")]
+    pub tick_rate: Option<u64>,
+
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, help = "Graph and average window, such as 60 or 5m [default: 60s]")]
+    pub window: Option<Duration>,
+
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, value_delimiter = ',', help = "Averaging windows shown per host [default: 2,10,40]")]
+    pub avg_windows: Vec<Duration>,
+
+    #[arg(short, long, value_enum, help = "Interactive TUI, or one JSON object / CSV rows per interval [default: tui]")]
+    pub output: Option<OutputMode>,
+
+    #[arg(long, hide = true, conflicts_with = "output")]
+    pub json: bool,
+
+    #[arg(long, value_name = "PATH", help = "Append headless records to PATH instead of stdout (implies --output json)")]
+    pub output_file: Option<PathBuf>,
+
+    #[arg(long, value_name = "SECS", help = "Seconds between headless records [default: the tick rate]")]
+    pub interval: Option<f64>,
+
+    #[arg(long, value_name = "ADDR:PORT", help = "Serve Prometheus metrics on ADDR:PORT/metrics")]
+    pub metrics: Option<SocketAddr>,
+
+    #[arg(long, value_name = "HOST:PORT", help = "Export flow records to this NetFlow/IPFIX collector")]
+    pub netflow: Option<String>,
+
+    #[arg(long, value_name = "VERSION", help = "5, 9 or ipfix [default: 9]")]
+    pub netflow_version: Option<FlowVersion>,
+
+    #[arg(long, value_name = "SECS", help = "Export long-lived flows every SECS [default: 60]")]
+    pub active_timeout: Option<u64>,
+
+    #[arg(long, value_name = "SECS", help = "Export flows idle for SECS [default: 15]")]
+    pub inactive_timeout: Option<u64>,
+
+    #[arg(long, value_name = "PATH", value_delimiter = ',', help = "MaxMind .mmdb (country or ASN) or CSV/TSV ASN table to annotate external addresses with, no network lookups")]
+    pub geoip: Vec<PathBuf>,
+
+    #[arg(long, global = true, value_name = "PATH", help = "Persist per-host and per-interface history to this SQLite database")]
+    pub db: Option<PathBuf>,
+
+    #[arg(short, long, global = true, value_name = "PATH", help = "TOML file providing defaults for these options")]
+    pub config: Option<PathBuf>,
+}
+
+// A config value in the same syntax as the matching flag
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, String>
+where
+    T::Err: ToString,
+{
+    value.parse().map_err(|e: T::Err| format!("invalid {} '{}' in config: {}", key, value, e.to_string()))
+}
+
+impl MonitorArgs {
+    // Fill every option not given on the command line from the config file
+    pub fn apply_config(&mut self, config: &Config) -> Result<(), Box<dyn Error>> {
+        if self.cidrs.is_empty() && self.include.is_empty() {
+            self.include = config.include.iter().map(|v| parse("include", v)).collect::<Result<_, _>>()?;
+        }
+        if self.exclude.is_empty() {
+            self.exclude = config.exclude.iter().map(|v| parse("exclude", v)).collect::<Result<_, _>>()?;
+        }
+        if self.interfaces.is_empty() && self.read.is_none() {
+            self.interfaces = config.interface.clone();
+        }
+        if self.avg_windows.is_empty() {
+            self.avg_windows = config.avg_windows.iter().map(|v| parse_duration(v)).collect::<Result<_, _>>()?;
+        }
+        // --filter and --no-auto-filter pick the capture filter together: either one on the command line overrides
+        // both config keys, and a config filter beats the config's no-auto-filter like the flags conflict
+        if self.filter.is_none() && !self.no_auto_filter {
+            self.filter = config.filter.clone();
+            self.no_auto_filter = config.no_auto_filter && self.filter.is_none();
+        }
+        self.tick_rate = self.tick_rate.or(config.tick_rate);
+        if self.window.is_none() {
+            self.window = config.window.as_deref().map(parse_duration).transpose()?;
+        }
+        if self.output.is_none() && !self.json {
+            self.output = config.output.as_deref().map(|v| OutputMode::from_str(v, true)).transpose()
+                .map_err(|e| format!("invalid output in config: {}", e))?;
+        }
+        self.output_file = self.output_file.take().or_else(|| config.output_file.clone());
+        self.interval = self.interval.or(config.interval);
+        if self.metrics.is_none() {
+            self.metrics = config.metrics.as_deref().map(|v| parse("metrics", v)).transpose()?;
+        }
+        self.netflow = self.netflow.take().or_else(|| config.netflow.clone());
+        if self.netflow_version.is_none() {
+            self.netflow_version = config.netflow_version.as_deref().map(|v| parse("netflow-version", v)).transpose()?;
+        }
+        self.active_timeout = self.active_timeout.or(config.active_timeout);
+        self.inactive_timeout = self.inactive_timeout.or(config.inactive_timeout);
+        self.db = self.db.take().or_else(|| config.db.clone());
+        if self.geoip.is_empty() {
+            self.geoip = config.geoip.clone();
+        }
+        Ok(())
+    }
+
+    pub fn output_mode(&self) -> OutputMode {
+        match self.output {
+            Some(mode) => mode,
+            // Writing to a file only makes sense headless
+            None if self.json || self.output_file.is_some() => OutputMode::Json,
+            None => OutputMode::Tui,
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use std::{collections::BTreeMap, error::Error, fs, path::{Path, PathBuf}};
+use serde::Deserialize;
+
+// Defaults for the command-line options, read from a TOML file. Keys are the long flag names:
+//
+//   interface = ["eth0", "wg0"]
+//   include = ["192.168.1.0/24"]
+//   exclude = ["192.168.1.1/32"]
+//   tick-rate = 1000
+//   output = "json"
+//
+// followed by the [hosts], [groups] and [display] tables
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub interface: Vec<String>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub filter: Option<String>,
+    pub no_auto_filter: bool,
+    pub tick_rate: Option<u64>,
+    pub window: Option<String>,
+    pub avg_windows: Vec<String>,
+    pub output: Option<String>,
+    pub output_file: Option<PathBuf>,
+    pub interval: Option<f64>,
+    pub metrics: Option<String>,
+    pub netflow: Option<String>,
+    pub netflow_version: Option<String>,
+    pub active_timeout: Option<u64>,
+    pub inactive_timeout: Option<u64>,
+    pub db: Option<PathBuf>,
+    pub geoip: Vec<PathBuf>,
+    // IP or MAC address -> label, e.g. "192.168.1.10" = "NAS"
+    pub hosts: BTreeMap<String, String>,
+    // Group name -> IP addresses, networks or MAC addresses, e.g. IoT = ["192.168.1.64/27"]
+    pub groups: BTreeMap<String, Vec<String>>,
+    pub display: Display,
+}
+
+// TUI preferences
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Display {
+    // View shown at startup: "talkers", "flows", "groups", "segments", "services", "conversations" or "destinations"
+    pub view: Option<String>,
+    // Maximum number of rows in the talkers table
+    pub rows: Option<usize>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path).map_err(|e| format!("cannot read config {}: {}", path.display(), e))?;
+        Ok(toml::from_str(&text).map_err(|e| format!("invalid config {}: {}", path.display(), e))?)
+    }
+}
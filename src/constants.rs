@@ -1,3 +1,10 @@
-pub const TICK_RATE_MS: u64 = 500; //
-pub const HISTORY_WINDOW_SECS: u64 = 60;  
-pub const MAX_SAMPLES: usize = (HISTORY_WINDOW_SECS * 1000 / TICK_RATE_MS) as usize;
+pub const DEFAULT_TICK_RATE_MS: u64 = 500;
+pub const DEFAULT_HISTORY_WINDOW_SECS: u64 = 60;
+// Averaging windows shown side by side in the talkers table, iftop-style
+pub const DEFAULT_AVG_WINDOWS_SECS: [u64; 3] = [2, 10, 40];
+// Steps the TUI cycles through with +/- and [/]
+pub const TICK_RATE_STEPS_MS: [u64; 6] = [100, 250, 500, 1000, 2000, 5000];
+pub const HISTORY_WINDOW_STEPS_SECS: [u64; 6] = [30, 60, 120, 300, 600, 1800];
+pub const FLOW_IDLE_TIMEOUT_SECS: u64 = 60;
+// Flow and conversation table size limit; past it the longest idle entries are evicted
+pub const MAX_FLOWS: usize = 100_000;
@@ -0,0 +1,157 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::geo::{GeoDb, GeoInfo};
+
+// Host pair with the tracked end as `a`; between two tracked hosts, the lower address is `a`
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ConversationKey {
+    pub a: IpAddr,
+    pub b: IpAddr,
+}
+
+// Counters accumulated between two ticks
+#[derive(Clone, Copy)]
+pub struct ConversationDelta {
+    pub a_to_b: u64,
+    pub b_to_a: u64,
+    pub packets: u64,
+    pub last_seen: SystemTime,
+    // Both ends are tracked hosts
+    pub local: bool,
+}
+
+impl ConversationDelta {
+    pub fn new(ts: SystemTime, local: bool) -> Self {
+        Self { a_to_b: 0, b_to_a: 0, packets: 0, last_seen: ts, local }
+    }
+}
+
+// Lifetime counters of one conversation
+pub struct Conversation {
+    pub a_to_b: u64,
+    pub b_to_a: u64,
+    pub packets: u64,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+    pub local: bool,
+    // Country and AS of the external end, when a database knows it
+    pub remote: Option<GeoInfo>,
+    // Bytes/s in both directions during the last tick
+    pub rate: f64,
+}
+
+impl Conversation {
+    pub fn bytes(&self) -> u64 {
+        self.a_to_b + self.b_to_a
+    }
+}
+
+pub struct ConversationTable {
+    conversations: HashMap<ConversationKey, Conversation>,
+    idle_timeout: Duration,
+    max_conversations: usize,
+    // Conversations dropped to stay under `max_conversations`, and when that last happened
+    evicted: u64,
+    last_eviction: Option<Instant>,
+}
+
+impl ConversationTable {
+    pub fn new(idle_timeout: Duration, max_conversations: usize) -> Self {
+        Self {
+            conversations: HashMap::new(),
+            idle_timeout,
+            max_conversations,
+            evicted: 0,
+            last_eviction: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.conversations.len()
+    }
+
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    pub fn last_eviction(&self) -> Option<Instant> {
+        self.last_eviction
+    }
+
+    // Both ends of every conversation
+    pub fn addresses(&self) -> HashSet<IpAddr> {
+        self.conversations.keys().flat_map(|key| [key.a, key.b]).collect()
+    }
+
+    pub fn get(&self, key: &ConversationKey) -> Option<&Conversation> {
+        self.conversations.get(key)
+    }
+
+    // Fold one tick worth of deltas into the table; new external peers are looked up in `geo` once
+    pub fn merge(&mut self, deltas: &HashMap<ConversationKey, ConversationDelta>, tick_secs: f64, geo: &GeoDb) {
+        for conversation in self.conversations.values_mut() {
+            conversation.rate = 0.0;
+        }
+        for (key, delta) in deltas {
+            let conversation = self.conversations.entry(*key).or_insert_with(|| Conversation {
+                a_to_b: 0,
+                b_to_a: 0,
+                packets: 0,
+                first_seen: delta.last_seen,
+                last_seen: delta.last_seen,
+                local: delta.local,
+                remote: if delta.local { None } else { geo.lookup(key.b) },
+                rate: 0.0,
+            });
+            conversation.a_to_b += delta.a_to_b;
+            conversation.b_to_a += delta.b_to_a;
+            conversation.packets += delta.packets;
+            conversation.last_seen = conversation.last_seen.max(delta.last_seen);
+            conversation.rate = (delta.a_to_b + delta.b_to_a) as f64 / tick_secs;
+        }
+
+        // A scan talks to one new peer per probe; like the flow table, the longest idle pairs make room
+        if self.conversations.len() > self.max_conversations {
+            let excess = self.conversations.len() - self.max_conversations;
+            let mut by_age: Vec<(SystemTime, ConversationKey)> =
+                self.conversations.iter().map(|(key, conversation)| (conversation.last_seen, *key)).collect();
+            by_age.select_nth_unstable_by_key(excess - 1, |(last_seen, _)| *last_seen);
+            for (_, key) in &by_age[..excess] {
+                self.conversations.remove(key);
+            }
+            self.evicted += excess as u64;
+            self.last_eviction = Some(Instant::now());
+        }
+    }
+
+    // Drop conversations that have been idle for longer than the timeout
+    pub fn expire(&mut self, now: SystemTime) {
+        let idle_timeout = self.idle_timeout;
+        self.conversations.retain(|_, conversation| {
+            now.duration_since(conversation.last_seen).map_or(true, |idle| idle <= idle_timeout)
+        });
+    }
+
+    // Busiest pairs first
+    pub fn top(&self, n: usize) -> Vec<(&ConversationKey, &Conversation)> {
+        let mut conversations: Vec<_> = self.conversations.iter().collect();
+        conversations.sort_by_key(|(_, conversation)| Reverse(conversation.bytes()));
+        conversations.truncate(n);
+        conversations
+    }
+
+    // Busiest conversations with external addresses, of one tracked host or of all of them
+    pub fn remotes(&self, host: Option<IpAddr>, n: usize) -> Vec<(&ConversationKey, &Conversation)> {
+        let mut conversations: Vec<_> = self.conversations.iter()
+            .filter(|(key, conversation)| !conversation.local && host.is_none_or(|host| key.a == host))
+            .collect();
+        conversations.sort_by_key(|(_, conversation)| Reverse(conversation.bytes()));
+        conversations.truncate(n);
+        conversations
+    }
+}
@@ -0,0 +1,198 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant, SystemTime},
+};
+
+// Unidirectional 5-tuple. Ports are 0 for protocols without them (ICMP, ...)
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct FlowKey {
+    pub protocol: u8,
+    pub src: IpAddr,
+    pub src_port: u16,
+    pub dst: IpAddr,
+    pub dst_port: u16,
+}
+
+impl FlowKey {
+    pub fn has_ports(&self) -> bool {
+        self.protocol == 6 || self.protocol == 17
+    }
+
+    pub fn src_endpoint(&self) -> String {
+        endpoint(self.src, self.src_port, self.has_ports())
+    }
+
+    pub fn dst_endpoint(&self) -> String {
+        endpoint(self.dst, self.dst_port, self.has_ports())
+    }
+}
+
+fn endpoint(ip: IpAddr, port: u16, has_ports: bool) -> String {
+    if has_ports {
+        SocketAddr::new(ip, port).to_string()
+    } else {
+        ip.to_string()
+    }
+}
+
+pub fn protocol_name(protocol: u8) -> &'static str {
+    match protocol {
+        1 => "ICMP",
+        2 => "IGMP",
+        6 => "TCP",
+        17 => "UDP",
+        47 => "GRE",
+        50 => "ESP",
+        58 => "ICMPv6",
+        132 => "SCTP",
+        _ => "Other",
+    }
+}
+
+// Counters accumulated by the capture thread between two ticks
+#[derive(Clone, Copy)]
+pub struct FlowDelta {
+    pub bytes: u64,
+    pub packets: u64,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+}
+
+impl FlowDelta {
+    pub fn new(ts: SystemTime) -> Self {
+        Self { bytes: 0, packets: 0, first_seen: ts, last_seen: ts }
+    }
+
+    pub fn add(&mut self, len: u64, ts: SystemTime) {
+        self.bytes += len;
+        self.packets += 1;
+        self.first_seen = self.first_seen.min(ts);
+        self.last_seen = self.last_seen.max(ts);
+    }
+
+    pub fn merge(&mut self, other: &FlowDelta) {
+        self.bytes += other.bytes;
+        self.packets += other.packets;
+        self.first_seen = self.first_seen.min(other.first_seen);
+        self.last_seen = self.last_seen.max(other.last_seen);
+    }
+}
+
+// Lifetime counters of one flow
+pub struct FlowStats {
+    pub bytes: u64,
+    pub packets: u64,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+    // Bytes/s during the last tick
+    pub rate: f64,
+}
+
+pub struct FlowTable {
+    flows: HashMap<FlowKey, FlowStats>,
+    idle_timeout: Duration,
+    max_flows: usize,
+    // Most recent packet timestamp seen, the clock used when replaying a capture file
+    latest_seen: Option<SystemTime>,
+    // Flows dropped to stay under `max_flows`, and when that last happened
+    evicted: u64,
+    last_eviction: Option<Instant>,
+}
+
+impl FlowTable {
+    pub fn new(idle_timeout: Duration, max_flows: usize) -> Self {
+        Self {
+            flows: HashMap::new(),
+            idle_timeout,
+            max_flows,
+            latest_seen: None,
+            evicted: 0,
+            last_eviction: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.flows.len()
+    }
+
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    pub fn last_eviction(&self) -> Option<Instant> {
+        self.last_eviction
+    }
+
+    pub fn latest_seen(&self) -> Option<SystemTime> {
+        self.latest_seen
+    }
+
+    // Fold one tick worth of deltas into the table
+    pub fn merge(&mut self, deltas: &HashMap<FlowKey, FlowDelta>, tick_secs: f64) {
+        for stats in self.flows.values_mut() {
+            stats.rate = 0.0;
+        }
+        for (key, delta) in deltas {
+            let stats = self.flows.entry(*key).or_insert_with(|| FlowStats {
+                bytes: 0,
+                packets: 0,
+                first_seen: delta.first_seen,
+                last_seen: delta.last_seen,
+                rate: 0.0,
+            });
+            stats.bytes += delta.bytes;
+            stats.packets += delta.packets;
+            stats.first_seen = stats.first_seen.min(delta.first_seen);
+            stats.last_seen = stats.last_seen.max(delta.last_seen);
+            stats.rate = delta.bytes as f64 / tick_secs;
+
+            self.latest_seen = Some(self.latest_seen.map_or(delta.last_seen, |t| t.max(delta.last_seen)));
+        }
+
+        // A port scan or flood creates one flow per 5-tuple; past the limit the longest idle ones make room
+        if self.flows.len() > self.max_flows {
+            let excess = self.flows.len() - self.max_flows;
+            let mut by_age: Vec<(SystemTime, FlowKey)> = self.flows.iter().map(|(key, stats)| (stats.last_seen, *key)).collect();
+            by_age.select_nth_unstable_by_key(excess - 1, |(last_seen, _)| *last_seen);
+            for (_, key) in &by_age[..excess] {
+                self.flows.remove(key);
+            }
+            self.evicted += excess as u64;
+            self.last_eviction = Some(Instant::now());
+        }
+    }
+
+    // Drop flows that have been idle for longer than the timeout
+    pub fn expire(&mut self, now: SystemTime) {
+        let idle_timeout = self.idle_timeout;
+        self.flows.retain(|_, stats| {
+            now.duration_since(stats.last_seen).map_or(true, |idle| idle <= idle_timeout)
+        });
+    }
+
+    // Bytes a host exchanged with each peer over its active flows, heaviest first
+    pub fn peers(&self, ip: IpAddr) -> Vec<(IpAddr, u64)> {
+        let mut peers: HashMap<IpAddr, u64> = HashMap::new();
+        for (key, stats) in &self.flows {
+            if key.src == ip {
+                *peers.entry(key.dst).or_default() += stats.bytes;
+            } else if key.dst == ip {
+                *peers.entry(key.src).or_default() += stats.bytes;
+            }
+        }
+        let mut peers: Vec<_> = peers.into_iter().collect();
+        peers.sort_by_key(|&(_, bytes)| Reverse(bytes));
+        peers
+    }
+
+    // Heaviest flows first
+    pub fn top(&self, n: usize) -> Vec<(&FlowKey, &FlowStats)> {
+        let mut flows: Vec<_> = self.flows.iter().collect();
+        flows.sort_by_key(|(_, stats)| Reverse(stats.bytes));
+        flows.truncate(n);
+        flows
+    }
+}
+
@@ -0,0 +1,221 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs,
+    net::IpAddr,
+    path::Path,
+};
+use maxminddb::{geoip2, Reader};
+
+// Country and autonomous system of an external address
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct GeoInfo {
+    // ISO 3166 code, e.g. "DE"
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+    pub org: Option<String>,
+}
+
+impl GeoInfo {
+    // "DE AS3320 Deutsche Telekom AG"
+    pub fn label(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(country) = &self.country {
+            parts.push(country.clone());
+        }
+        if let Some(asn) = self.asn {
+            parts.push(format!("AS{}", asn));
+        }
+        if let Some(org) = &self.org {
+            parts.push(org.clone());
+        }
+        parts.join(" ")
+    }
+
+    fn is_empty(&self) -> bool {
+        self.country.is_none() && self.asn.is_none() && self.org.is_none()
+    }
+
+    // Fill in what an earlier database did not know
+    fn fill(&mut self, other: GeoInfo) {
+        self.country = self.country.take().or(other.country);
+        self.asn = self.asn.or(other.asn);
+        self.org = self.org.take().or(other.org);
+    }
+}
+
+// One line of an ASN table: an inclusive address range of one family
+struct AsnRange {
+    start: u128,
+    end: u128,
+    info: GeoInfo,
+}
+
+fn ip_value(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(v4) => u32::from(v4) as u128,
+        IpAddr::V6(v6) => u128::from(v6),
+    }
+}
+
+// Local country/ASN databases; never does network lookups
+#[derive(Default)]
+pub struct GeoDb {
+    // MaxMind format databases with whether each is an ASN database
+    readers: Vec<(Reader<Vec<u8>>, bool)>,
+    // Sorted by start address
+    v4: Vec<AsnRange>,
+    v6: Vec<AsnRange>,
+}
+
+impl GeoDb {
+    // .mmdb files are MaxMind databases (GeoLite2-Country, -City or -ASN), anything else an ASN table
+    pub fn open(paths: &[impl AsRef<Path>]) -> Result<Self, Box<dyn Error>> {
+        let mut db = Self::default();
+        for path in paths {
+            let path = path.as_ref();
+            if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("mmdb")) {
+                let reader = Reader::open_readfile(path).map_err(|e| format!("cannot read GeoIP database {}: {}", path.display(), e))?;
+                let is_asn = reader.metadata.database_type.contains("ASN");
+                db.readers.push((reader, is_asn));
+            } else {
+                let text = fs::read_to_string(path).map_err(|e| format!("cannot read ASN table {}: {}", path.display(), e))?;
+                db.add_table(&text);
+            }
+        }
+        db.v4.sort_by_key(|range| range.start);
+        db.v6.sort_by_key(|range| range.start);
+        Ok(db)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.readers.is_empty() && self.v4.is_empty() && self.v6.is_empty()
+    }
+
+    // iptoasn.com layout, tab or comma separated: range_start, range_end, AS number, country, description.
+    // AS 0 marks unrouted space, '#' starts a comment and unparsable lines such as a header are skipped
+    fn add_table(&mut self, text: &str) {
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let separator = if line.contains('\t') { '\t' } else { ',' };
+            let fields: Vec<&str> = line.splitn(5, separator).map(|field| field.trim().trim_matches('"')).collect();
+            let [start, end, asn, rest @ ..] = fields.as_slice() else {
+                continue;
+            };
+            let (Ok(start), Ok(end), Ok(asn)) = (start.parse::<IpAddr>(), end.parse::<IpAddr>(), asn.trim_start_matches("AS").parse::<u32>()) else {
+                continue;
+            };
+            if asn == 0 || start.is_ipv4() != end.is_ipv4() {
+                continue;
+            }
+            let text = |index: usize| rest.get(index).filter(|v| !v.is_empty() && **v != "None" && **v != "Unknown").map(|v| v.to_string());
+            let info = GeoInfo { country: text(0), asn: Some(asn), org: text(1) };
+            let range = AsnRange { start: ip_value(start), end: ip_value(end), info };
+            if start.is_ipv4() { self.v4.push(range) } else { self.v6.push(range) }
+        }
+    }
+
+    fn table_lookup(&self, ip: IpAddr) -> Option<GeoInfo> {
+        let ranges = if ip.is_ipv4() { &self.v4 } else { &self.v6 };
+        let value = ip_value(ip);
+        let index = ranges.partition_point(|range| range.start <= value).checked_sub(1)?;
+        let range = &ranges[index];
+        (value <= range.end).then(|| range.info.clone())
+    }
+
+    // Everything the databases know about `ip`, None when none of them has it
+    pub fn lookup(&self, ip: IpAddr) -> Option<GeoInfo> {
+        let mut info = GeoInfo::default();
+        for (reader, is_asn) in &self.readers {
+            if *is_asn {
+                if let Ok(asn) = reader.lookup::<geoip2::Asn>(ip) {
+                    info.fill(GeoInfo {
+                        country: None,
+                        asn: asn.autonomous_system_number,
+                        org: asn.autonomous_system_organization.map(str::to_string),
+                    });
+                }
+            } else if let Ok(country) = reader.lookup::<geoip2::Country>(ip) {
+                let iso_code = country.country.or(country.registered_country).and_then(|c| c.iso_code);
+                info.fill(GeoInfo { country: iso_code.map(str::to_string), asn: None, org: None });
+            }
+        }
+        if let Some(range) = self.table_lookup(ip) {
+            info.fill(range);
+        }
+        (!info.is_empty()).then_some(info)
+    }
+}
+
+// Counters of one autonomous system accumulated between two ticks
+#[derive(Default)]
+pub struct AsnDelta {
+    // Bytes from tracked hosts to the AS, and back
+    pub sent: u64,
+    pub received: u64,
+    pub hosts: HashSet<IpAddr>,
+    pub info: GeoInfo,
+}
+
+// Lifetime counters of one autonomous system
+pub struct AsnStats {
+    pub org: Option<String>,
+    pub country: Option<String>,
+    pub sent: u64,
+    pub received: u64,
+    // Tracked hosts that exchanged traffic with it
+    pub hosts: HashSet<IpAddr>,
+    // Bytes/s in both directions during the last tick
+    pub rate: f64,
+}
+
+impl AsnStats {
+    pub fn bytes(&self) -> u64 {
+        self.sent + self.received
+    }
+}
+
+// Traffic between tracked hosts and each autonomous system, 0 for addresses without one
+#[derive(Default)]
+pub struct AsnTable {
+    asns: HashMap<u32, AsnStats>,
+}
+
+impl AsnTable {
+    pub fn len(&self) -> usize {
+        self.asns.len()
+    }
+
+    // Fold one tick worth of deltas into the table
+    pub fn merge(&mut self, deltas: HashMap<u32, AsnDelta>, tick_secs: f64) {
+        for stats in self.asns.values_mut() {
+            stats.rate = 0.0;
+        }
+        for (asn, delta) in deltas {
+            let stats = self.asns.entry(asn).or_insert_with(|| AsnStats {
+                org: delta.info.org,
+                country: delta.info.country,
+                sent: 0,
+                received: 0,
+                hosts: HashSet::new(),
+                rate: 0.0,
+            });
+            stats.sent += delta.sent;
+            stats.received += delta.received;
+            stats.hosts.extend(delta.hosts);
+            stats.rate = (delta.sent + delta.received) as f64 / tick_secs;
+        }
+    }
+
+    // Heaviest autonomous systems first
+    pub fn top(&self, n: usize) -> Vec<(u32, &AsnStats)> {
+        let mut asns: Vec<_> = self.asns.iter().map(|(asn, stats)| (*asn, stats)).collect();
+        asns.sort_by_key(|(_, stats)| Reverse(stats.bytes()));
+        asns.truncate(n);
+        asns
+    }
+}
@@ -0,0 +1,295 @@
+use std::{
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Receiver,
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use chrono::{DateTime, Local};
+use serde_json::{json, Value};
+
+use crate::app::{App, InterfaceStats, StatsBatch};
+use crate::geo::GeoInfo;
+use crate::services::ServiceTable;
+
+// Number of talkers included in each record, same as the TUI table
+const TOP_TALKERS: usize = 25;
+// Services listed per talker, and for the whole network
+const TALKER_SERVICES: usize = 5;
+const TOP_SERVICES: usize = 25;
+// Busiest host pairs included in each record
+const TOP_CONVERSATIONS: usize = 25;
+// External addresses listed per talker, and autonomous systems for the whole network
+const TALKER_DESTINATIONS: usize = 5;
+const TOP_ASNS: usize = 25;
+
+const CSV_HEADER: &str = "timestamp,ip,hostname,label,group,mac,vendor,avg_rx_bytes_per_sec,avg_tx_bytes_per_sec,peak_bytes_per_sec,total_rx_bytes,total_tx_bytes";
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    // One JSON object per interval
+    Json,
+    // One row per top talker per interval
+    Csv,
+}
+
+// Run the same tick aggregation as the TUI, writing records every interval instead of drawing
+pub fn run(
+    mut app: App,
+    stats: Vec<Receiver<StatsBatch>>,
+    mut out: Box<dyn Write>,
+    interval: Duration,
+    format: Format,
+) -> io::Result<()> {
+    let running = Arc::new(AtomicBool::new(true));
+    let handler_flag = Arc::clone(&running);
+    ctrlc::set_handler(move || handler_flag.store(false, Ordering::SeqCst))
+        .map_err(io::Error::other)?;
+
+    let mut last_report = Instant::now();
+    let mut reported_lost = 0;
+    let mut reported_evicted = 0;
+    let mut reported_conversations_evicted = 0;
+    let mut reported_history_error = None;
+    if let Format::Csv = format {
+        writeln!(out, "{}", CSV_HEADER)?;
+    }
+
+    while running.load(Ordering::SeqCst) {
+        thread::sleep(app.timing.tick_rate.saturating_sub(app.last_tick.elapsed()));
+        app.on_tick(&stats);
+        // Once a replayed file has been read to the end, write what it added up to and stop
+        let finished = app.finished();
+
+        if finished || last_report.elapsed() >= interval {
+            match format {
+                Format::Json => writeln!(out, "{}", snapshot(&app))?,
+                Format::Csv => write_csv_rows(&mut out, &app)?,
+            }
+            out.flush()?;
+            last_report = Instant::now();
+
+            // CSV has no column for it, and a daemon's stderr usually ends up in a log anyway
+            if let Some(capture) = app.aggregate.capture {
+                if capture.lost() > reported_lost {
+                    eprintln!("Warning: the kernel dropped {} packets since the last record, rates are underestimated", capture.lost() - reported_lost);
+                    reported_lost = capture.lost();
+                }
+            }
+            if app.flows.evicted() > reported_evicted {
+                eprintln!("Warning: the flow table is full, {} flows were evicted since the last record", app.flows.evicted() - reported_evicted);
+                reported_evicted = app.flows.evicted();
+            }
+            if app.conversations.evicted() > reported_conversations_evicted {
+                eprintln!(
+                    "Warning: the conversation table is full, {} conversations were evicted since the last record",
+                    app.conversations.evicted() - reported_conversations_evicted
+                );
+                reported_conversations_evicted = app.conversations.evicted();
+            }
+            if app.history_error.is_some() && app.history_error != reported_history_error {
+                eprintln!("Warning: could not write traffic history, will retry: {}", app.history_error.as_deref().unwrap_or_default());
+            }
+            reported_history_error = app.history_error.clone();
+        }
+        if finished {
+            for iface in &app.interfaces {
+                if let Some(error) = &iface.error {
+                    eprintln!("Error: reading {} stopped early: {}", iface.name, error);
+                }
+            }
+            break;
+        }
+    }
+
+    app.shutdown();
+    if let Some(err) = &app.history_error {
+        eprintln!("Warning: the last traffic history write failed: {}", err);
+    }
+    out.flush()
+}
+
+// Kernel capture counters, null when replaying a file
+fn capture_json(iface: &InterfaceStats) -> Value {
+    match iface.capture {
+        Some(capture) => json!({
+            "received": capture.received,
+            "dropped": capture.dropped,
+            "if_dropped": capture.if_dropped,
+            "drops_rising": iface.drops_rising(),
+        }),
+        None => Value::Null,
+    }
+}
+
+fn interface_json(iface: &InterfaceStats) -> Value {
+    json!({
+        "name": iface.name,
+        "rx_bytes_per_sec": iface.current_rx_rate(),
+        "tx_bytes_per_sec": iface.current_tx_rate(),
+        "total_rx_bytes": iface.total_rx_bytes,
+        "total_tx_bytes": iface.total_tx_bytes,
+        "fwd_bytes_per_sec": iface.fwd_rate,
+        "total_fwd_bytes": iface.total_fwd_bytes,
+        "capture": capture_json(iface),
+    })
+}
+
+fn services_json(services: &ServiceTable, n: usize) -> Vec<Value> {
+    services.top(n).into_iter().map(|(service, stats)| {
+        json!({
+            "service": service.name(),
+            "protocol": service.class(),
+            "port": service.port,
+            "bytes_per_sec": stats.rate,
+            "total_bytes": stats.bytes,
+            "packets": stats.packets,
+        })
+    }).collect()
+}
+
+// Global rates and totals, per-interface figures and the top talkers at this instant
+pub fn snapshot(app: &App) -> Value {
+    let global = &app.aggregate;
+    let talkers: Vec<Value> = app.top_talkers.iter().take(TOP_TALKERS).map(|talker| {
+        // Keyed by window length in seconds, e.g. {"2": ..., "10": ..., "40": ...}
+        let windows: serde_json::Map<String, Value> = app.timing.avg_windows.iter().zip(&talker.window_rates)
+            .map(|(window, rate)| (window.as_secs().to_string(), json!(rate)))
+            .collect();
+        json!({
+            "ip": talker.ip.to_string(),
+            "hostname": talker.name,
+            "label": talker.label,
+            "group": talker.group,
+            "mac": talker.mac.map(|m| m.to_string()),
+            "vendor": talker.vendor,
+            "avg_rx_bytes_per_sec": talker.avg_rx,
+            "avg_tx_bytes_per_sec": talker.avg_tx,
+            "window_bytes_per_sec": windows,
+            "peak_bytes_per_sec": talker.peak_rate,
+            "peak_time": talker.peak_time.to_rfc3339(),
+            "total_rx_bytes": talker.total_rx_bytes,
+            "total_tx_bytes": talker.total_tx_bytes,
+            "services": app.host(&talker.ip).map(|h| services_json(&h.services, TALKER_SERVICES)).unwrap_or_default(),
+            "remote_destinations": app.conversations.remotes(Some(talker.ip), TALKER_DESTINATIONS).into_iter().map(|(key, conversation)| {
+                json!({
+                    "ip": key.b.to_string(),
+                    "geo": geo_json(conversation.remote.as_ref()),
+                    "bytes_per_sec": conversation.rate,
+                    "sent_bytes": conversation.a_to_b,
+                    "received_bytes": conversation.b_to_a,
+                })
+            }).collect::<Vec<_>>(),
+        })
+    }).collect();
+    let protocols: serde_json::Map<String, Value> = app.services.classes().into_iter()
+        .map(|(class, stats)| (class.to_string(), json!({"bytes_per_sec": stats.rate, "total_bytes": stats.bytes, "packets": stats.packets})))
+        .collect();
+
+    // VLANs and VXLAN/GENEVE networks, empty unless the traffic is tagged or tunnelled
+    let segments: Vec<Value> = app.segments.top(app.segments.len()).into_iter().map(|(segment, stats)| {
+        json!({
+            "kind": segment.kind(),
+            "id": segment.id(),
+            "bytes_per_sec": stats.rate,
+            "total_bytes": stats.bytes,
+            "packets": stats.packets,
+            "hosts": stats.hosts.len(),
+        })
+    }).collect();
+
+    // Host pairs with the tracked end as "host"; "scope" is "local" when both ends are tracked
+    let conversations: Vec<Value> = app.conversations.top(TOP_CONVERSATIONS).into_iter().map(|(key, conversation)| {
+        json!({
+            "host": key.a.to_string(),
+            "peer": key.b.to_string(),
+            "scope": if conversation.local { "local" } else { "external" },
+            "bytes_per_sec": conversation.rate,
+            "host_to_peer_bytes": conversation.a_to_b,
+            "peer_to_host_bytes": conversation.b_to_a,
+            "packets": conversation.packets,
+            "peer_geo": (!conversation.local).then(|| geo_json(conversation.remote.as_ref())),
+            "first_seen": DateTime::<Local>::from(conversation.first_seen).to_rfc3339(),
+            "last_seen": DateTime::<Local>::from(conversation.last_seen).to_rfc3339(),
+        })
+    }).collect();
+
+    // Traffic per autonomous system, empty without a --geoip database; asn 0 collects unknown addresses
+    let asns: Vec<Value> = app.asns.top(TOP_ASNS).into_iter().map(|(asn, stats)| {
+        json!({
+            "asn": asn,
+            "org": stats.org,
+            "country": stats.country,
+            "hosts": stats.hosts.len(),
+            "bytes_per_sec": stats.rate,
+            "sent_bytes": stats.sent,
+            "received_bytes": stats.received,
+        })
+    }).collect();
+
+    json!({
+        "timestamp": Local::now().to_rfc3339(),
+        "rx_bytes_per_sec": global.current_rx_rate(),
+        "tx_bytes_per_sec": global.current_tx_rate(),
+        "total_rx_bytes": global.total_rx_bytes,
+        "total_tx_bytes": global.total_tx_bytes,
+        "fwd_bytes_per_sec": global.fwd_rate,
+        "total_fwd_bytes": global.total_fwd_bytes,
+        "capture": capture_json(global),
+        "flows_active": app.flows.len(),
+        "flows_evicted": app.flows.evicted(),
+        "conversations_active": app.conversations.len(),
+        "conversations_evicted": app.conversations.evicted(),
+        "interfaces": app.interfaces.iter().map(interface_json).collect::<Vec<_>>(),
+        "top_talkers": talkers,
+        "segments": segments,
+        "protocols": protocols,
+        "services": services_json(&app.services, TOP_SERVICES),
+        "conversations": conversations,
+        "asns": asns,
+    })
+}
+
+// Country and AS of an external address, null fields when unknown
+fn geo_json(info: Option<&GeoInfo>) -> Value {
+    json!({
+        "country": info.and_then(|i| i.country.clone()),
+        "asn": info.and_then(|i| i.asn),
+        "org": info.and_then(|i| i.org.clone()),
+    })
+}
+
+// Quote a CSV field when it contains a separator, quote or newline
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn write_csv_rows(out: &mut dyn Write, app: &App) -> io::Result<()> {
+    let timestamp = Local::now().to_rfc3339();
+    for talker in app.top_talkers.iter().take(TOP_TALKERS) {
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{:.0},{:.0},{:.0},{},{}",
+            timestamp,
+            talker.ip,
+            csv_field(talker.name.as_deref().unwrap_or("")),
+            csv_field(talker.label.as_deref().unwrap_or("")),
+            csv_field(talker.group.as_deref().unwrap_or("")),
+            talker.mac.map(|m| m.to_string()).unwrap_or_default(),
+            csv_field(talker.vendor.unwrap_or("")),
+            talker.avg_rx,
+            talker.avg_tx,
+            talker.peak_rate,
+            talker.total_rx_bytes,
+            talker.total_tx_bytes,
+        )?;
+    }
+    Ok(())
+}
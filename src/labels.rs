@@ -0,0 +1,81 @@
+use std::{collections::HashMap, error::Error, net::IpAddr};
+use pnet::ipnetwork::IpNetwork;
+use pnet::util::MacAddr;
+
+use crate::config::Config;
+
+// What a host label or group member refers to
+enum Selector {
+    Network(IpNetwork),
+    Mac(MacAddr),
+}
+
+impl Selector {
+    // MAC addresses are tried first so that "aa:bb:cc:dd:ee:ff" is not mistaken for an IPv6 address
+    fn parse(value: &str) -> Result<Self, String> {
+        if let Ok(mac) = value.parse::<MacAddr>() {
+            return Ok(Selector::Mac(mac));
+        }
+        value.parse::<IpNetwork>()
+            .map(Selector::Network)
+            .map_err(|_| format!("'{}' is neither an IP address, a network nor a MAC address", value))
+    }
+
+    fn matches(&self, ip: &IpAddr, mac: Option<MacAddr>) -> bool {
+        match self {
+            Selector::Network(network) => network.contains(*ip),
+            Selector::Mac(selector) => mac == Some(*selector),
+        }
+    }
+}
+
+// User-given host names and groups from the config file
+#[derive(Default)]
+pub struct HostLabels {
+    by_ip: HashMap<IpAddr, String>,
+    by_mac: HashMap<MacAddr, String>,
+    // Sorted by name; a host belongs to the first group with a matching member
+    groups: Vec<(String, Vec<Selector>)>,
+}
+
+impl HostLabels {
+    pub fn from_config(config: &Config) -> Result<Self, Box<dyn Error>> {
+        let mut labels = HostLabels::default();
+        for (key, label) in &config.hosts {
+            match Selector::parse(key).map_err(|e| format!("invalid [hosts] entry: {}", e))? {
+                Selector::Mac(mac) => { labels.by_mac.insert(mac, label.clone()); }
+                Selector::Network(network) if network.prefix() == max_prefix(&network) => {
+                    labels.by_ip.insert(network.ip(), label.clone());
+                }
+                Selector::Network(network) => return Err(format!("[hosts] entry '{}' must be a single address", network).into()),
+            }
+        }
+        for (name, members) in &config.groups {
+            let selectors = members.iter()
+                .map(|member| Selector::parse(member).map_err(|e| format!("invalid member of group '{}': {}", name, e)))
+                .collect::<Result<_, _>>()?;
+            labels.groups.push((name.clone(), selectors));
+        }
+        Ok(labels)
+    }
+
+    // A label set for the IP takes precedence over one set for the MAC
+    pub fn label(&self, ip: &IpAddr, mac: Option<MacAddr>) -> Option<&str> {
+        self.by_ip.get(ip)
+            .or_else(|| mac.and_then(|mac| self.by_mac.get(&mac)))
+            .map(String::as_str)
+    }
+
+    pub fn group(&self, ip: &IpAddr, mac: Option<MacAddr>) -> Option<&str> {
+        self.groups.iter()
+            .find(|(_, selectors)| selectors.iter().any(|s| s.matches(ip, mac)))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+fn max_prefix(network: &IpNetwork) -> u8 {
+    match network {
+        IpNetwork::V4(_) => 32,
+        IpNetwork::V6(_) => 128,
+    }
+}
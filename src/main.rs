@@ -1,52 +1,222 @@
 mod app;
+mod bench;
+mod cli;
+mod config;
 mod constants;
+mod conversation;
+mod flow;
+mod geo;
+mod headless;
+mod labels;
+mod metrics;
+mod names;
+mod netflow;
 mod network;
+mod oui;
+mod segment;
+mod services;
+mod store;
 mod ui;
 mod util;
 
 use std::{
-    collections::HashMap,
     error::Error,
+    fs::OpenOptions,
+    io::{self, Write},
+    net::ToSocketAddrs,
+    process,
     sync::{Arc, Mutex},
+    time::Duration,
 };
-use app::SharedStats;
-use pnet::ipnetwork::Ipv4Network;
-
-fn main() -> Result<(), Box<dyn Error>> {
-    let args: Vec<String> = std::env::args().collect();
-    let cidr_arg = args.get(1);
-
-    let filter_cidr: Option<Ipv4Network> = match cidr_arg {
-        Some(s) => {
-            match s.parse() {
-                Ok(net) => {
-                    println!("Filter mode: Targeting subnet {}", net);
-                    Some(net)
-                },
-                Err(_) => {
-                    eprintln!("Invalid CIDR provided '{}', falling back to default private ranges.", s);
-                    None
-                }
-            }
-        },
-        None => {
-            println!("No subnet provided. Targeting all standard private networks (RFC1918).");
-            None
+use clap::Parser;
+use app::{App, Timing, View};
+use cli::{Cli, Command, OutputMode};
+use config::Config;
+use geo::GeoDb;
+use labels::HostLabels;
+use netflow::{FlowExporter, FlowVersion};
+use network::{CaptureContext, CaptureFilter, LocalAddrs, PlaybackSpeed, TrackFilter};
+use store::HistoryStore;
+
+fn main() {
+    // Print errors with Display rather than the Debug output of returning them from main
+    if let Err(err) = run(Cli::parse()) {
+        eprintln!("Error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let mut args = cli.monitor;
+    let config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    args.apply_config(&config)?;
+
+    match cli.command {
+        Some(Command::ListInterfaces) => return network::print_interfaces(),
+        Some(Command::Report { period }) => {
+            let path = args.db.ok_or("report requires --db PATH")?;
+            return print_report(&HistoryStore::open(&path)?, period);
+        }
+        Some(Command::Bench { packets, hosts }) => return bench::run(packets, hosts),
+        None => {}
+    }
+
+    let labels = HostLabels::from_config(&config)?;
+    let view: Option<View> = config.display.view.as_deref().map(str::parse).transpose()?;
+
+    let output = args.output_mode();
+    if output == OutputMode::Tui && args.output_file.is_some() {
+        return Err("--output-file needs --output json or csv".into());
+    }
+
+    let mut timing = Timing::default();
+    if let Some(ms) = args.tick_rate {
+        if ms == 0 {
+            return Err("tick-rate must be at least 1 ms".into());
         }
+        timing.tick_rate = Duration::from_millis(ms);
+    }
+    if let Some(window) = args.window {
+        timing.history_window = window;
+    }
+    if !args.avg_windows.is_empty() {
+        timing.avg_windows = args.avg_windows;
+    }
+    let interval = match args.interval {
+        Some(0.0) => return Err("interval must be greater than 0 seconds".into()),
+        Some(secs) => Duration::try_from_secs_f64(secs).map_err(|_| format!("invalid --interval {}", secs))?,
+        None => timing.tick_rate,
+    };
+
+    let netflow_collector = match &args.netflow {
+        Some(collector) => Some(
+            collector.to_socket_addrs()
+                .map_err(|e| format!("invalid collector address '{}': {}", collector, e))?
+                .next()
+                .ok_or_else(|| format!("collector address '{}' did not resolve", collector))?,
+        ),
+        None => None,
+    };
+
+    let mut include = args.cidrs;
+    include.extend(args.include);
+    let track = TrackFilter { include, exclude: args.exclude };
+    if track.include.is_empty() {
+        eprintln!("No subnet provided. Targeting all standard private networks (RFC1918, IPv6 link-local and ULA).");
+    } else {
+        eprintln!("Filter mode: Targeting {}", join(&track.include));
+    }
+    if !track.exclude.is_empty() {
+        eprintln!("Excluding {}", join(&track.exclude));
+    }
+    let filter = match args.filter {
+        Some(program) => Some(CaptureFilter::Custom(program)),
+        None if args.no_auto_filter => None,
+        None => Some(CaptureFilter::Tracked),
     };
-    // network module to get default device and local IP
-    let (device, local_ip) = network::get_default_device()?;
-    let device_name = device.name.clone();
+    let filter = filter.as_ref();
+
+    // one channel from each capture thread to the UI thread
+    let mut stats = Vec::new();
+    let mut source_names = Vec::new();
 
-    // shared stats between capture thread and UI thread
-    let stats = Arc::new(Mutex::new(SharedStats {
-        traffic_delta: HashMap::new(),
-        rx_delta: 0,
-        tx_delta: 0,
-    }));
+    let offline = args.read.is_some();
+    match args.read {
+        Some(path) => {
+            // Offline mode: no local interface, so every packet counts as RX
+            let ctx = CaptureContext { local: LocalAddrs::default(), track };
+            let (sender, receiver) = network::stats_channel();
+            let speed = args.speed.unwrap_or(PlaybackSpeed::Realtime);
+            network::start_file_capture_thread(&path, speed, filter, ctx, sender)?;
+            stats.push(receiver);
+            source_names.push(format!("file: {}", path.display()));
+        }
+        None => {
+            // network module to get the capture devices and their local IPs
+            let devices = if args.interfaces.is_empty() {
+                vec![network::get_default_device()?]
+            } else {
+                args.interfaces.iter().map(|name| network::get_device(name)).collect::<Result<Vec<_>, _>>()?
+            };
+            for (device, local) in devices {
+                let device_name = device.name.clone();
+                let ctx = CaptureContext { local, track: track.clone() };
+                let (sender, receiver) = network::stats_channel();
+                network::start_capture_thread(device, filter, ctx, sender)?;
+                stats.push(receiver);
+                source_names.push(device_name);
+            }
+        }
+    }
+
+    let mut app = App::new(&source_names, timing);
+    app.offline = offline;
+    app.labels = labels;
+    if let Some(view) = view {
+        app.view = view;
+    }
+    app.talker_rows = config.display.rows;
+
+    if let Some(addr) = args.metrics {
+        let snapshot = Arc::new(Mutex::new(String::new()));
+        metrics::serve(addr, Arc::clone(&snapshot))?;
+        app.metrics = Some(snapshot);
+        eprintln!("Serving Prometheus metrics on http://{}/metrics", addr);
+    }
+
+    if !args.geoip.is_empty() {
+        app.geo = GeoDb::open(&args.geoip)?;
+    }
+
+    if let Some(path) = args.db {
+        app.attach_history(HistoryStore::open(&path)?)?;
+    }
 
-    network::start_capture_thread(device, local_ip, Arc::clone(&stats), filter_cidr)?;
-    ui::run(stats, &device_name)?;
+    if let Some(collector) = netflow_collector {
+        let netflow_version = args.netflow_version.unwrap_or(FlowVersion::V9);
+        let active_timeout = Duration::from_secs(args.active_timeout.unwrap_or(60));
+        let inactive_timeout = Duration::from_secs(args.inactive_timeout.unwrap_or(15));
+        app.flow_exporter = Some(FlowExporter::new(collector, netflow_version, active_timeout, inactive_timeout)?);
+        eprintln!("Exporting {:?} flow records to {}", netflow_version, collector);
+    }
 
+    let format = match output {
+        OutputMode::Tui => return Ok(ui::run(app, stats)?),
+        OutputMode::Json => headless::Format::Json,
+        OutputMode::Csv => headless::Format::Csv,
+    };
+    // Headless mode: records to stdout or appended to a file
+    let out: Box<dyn Write> = match &args.output_file {
+        Some(path) => Box::new(
+            OpenOptions::new().create(true).append(true).open(path)
+                .map_err(|e| format!("cannot open {}: {}", path.display(), e))?,
+        ),
+        None => Box::new(io::stdout()),
+    };
+    headless::run(app, stats, out, interval, format)?;
+
+    Ok(())
+}
+
+// Top hosts by traffic over the last `period`, from the on-disk history
+fn print_report(store: &HistoryStore, period: Duration) -> Result<(), Box<dyn Error>> {
+    println!("{:<40} {:<24} {:>12} {:>12} {:>12}", "Host", "Name", "RX", "TX", "Total");
+    for host in store.top_hosts(period, 25)? {
+        println!(
+            "{:<40} {:<24} {:>12} {:>12} {:>12}",
+            host.ip,
+            host.name.unwrap_or_default(),
+            util::format_bytes_total(host.rx_bytes),
+            util::format_bytes_total(host.tx_bytes),
+            util::format_bytes_total(host.rx_bytes + host.tx_bytes)
+        );
+    }
     Ok(())
 }
+
+fn join<T: ToString>(items: &[T]) -> String {
+    items.iter().map(T::to_string).collect::<Vec<_>>().join(", ")
+}
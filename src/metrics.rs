@@ -0,0 +1,144 @@
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::app::{App, InterfaceStats, IpHistory};
+
+// Latest rendered exposition text, refreshed every tick and served as-is to scrapers
+pub type MetricsSnapshot = Arc<Mutex<String>>;
+
+// Escape a label value per the Prometheus text format
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// One metric family and how to read its value from T
+struct Metric<T> {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+    value: fn(&T) -> f64,
+}
+
+impl<T> Metric<T> {
+    const fn new(name: &'static str, kind: &'static str, help: &'static str, value: fn(&T) -> f64) -> Self {
+        Self { name, kind, help, value }
+    }
+
+    fn header(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} {}", self.name, self.kind);
+    }
+}
+
+const INTERFACE_METRICS: [Metric<InterfaceStats>; 6] = [
+    Metric::new("net_monitor_interface_rx_bytes_total", "counter", "Bytes received on the interface.", |i| i.total_rx_bytes as f64),
+    Metric::new("net_monitor_interface_tx_bytes_total", "counter", "Bytes transmitted on the interface.", |i| i.total_tx_bytes as f64),
+    Metric::new("net_monitor_interface_rx_bytes_per_second", "gauge", "Receive rate during the last tick.", |i| i.current_rx_rate()),
+    Metric::new("net_monitor_interface_tx_bytes_per_second", "gauge", "Transmit rate during the last tick.", |i| i.current_tx_rate()),
+    Metric::new("net_monitor_interface_forwarded_bytes_total", "counter", "Bytes neither from nor to this host.", |i| i.total_fwd_bytes as f64),
+    Metric::new("net_monitor_interface_forwarded_bytes_per_second", "gauge", "Forwarded rate during the last tick.", |i| i.fwd_rate),
+];
+
+const HOST_METRICS: [Metric<IpHistory>; 4] = [
+    Metric::new("net_monitor_host_rx_bytes_total", "counter", "Bytes received by the host.", |h| h.total_rx_bytes as f64),
+    Metric::new("net_monitor_host_tx_bytes_total", "counter", "Bytes sent by the host.", |h| h.total_tx_bytes as f64),
+    Metric::new("net_monitor_host_rx_bytes_per_second", "gauge", "Host receive rate during the last tick.", |h| h.current_rates().0),
+    Metric::new("net_monitor_host_tx_bytes_per_second", "gauge", "Host send rate during the last tick.", |h| h.current_rates().1),
+];
+
+// Render interface and per-host counters in the Prometheus text exposition format
+pub fn render(app: &App) -> String {
+    let mut out = String::new();
+
+    for metric in &INTERFACE_METRICS {
+        metric.header(&mut out);
+        for iface in &app.interfaces {
+            let _ = writeln!(out, "{}{{interface=\"{}\"}} {}", metric.name, escape(&iface.name), (metric.value)(iface));
+        }
+    }
+
+    for metric in &HOST_METRICS {
+        metric.header(&mut out);
+        for (ip, history) in app.hosts() {
+            let hostname = app.hostnames.get(ip).and_then(|names| names.best()).unwrap_or("");
+            let interface = app.interfaces.get(history.interface).map_or("", |i| i.name.as_str());
+            let _ = writeln!(
+                out,
+                "{}{{ip=\"{}\",hostname=\"{}\",interface=\"{}\"}} {}",
+                metric.name, ip, escape(hostname), escape(interface), (metric.value)(history)
+            );
+        }
+    }
+
+    out
+}
+
+fn handle(stream: TcpStream, snapshot: &MetricsSnapshot) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+
+    // Only the request line matters; drain the headers so the client sees a clean close
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", "text/plain; version=0.0.4", snapshot.lock().unwrap().clone()),
+        _ => ("404 Not Found", "text/plain", "Not found, try /metrics\n".to_string()),
+    };
+
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, content_type, body.len(), body
+    )?;
+    stream.flush()
+}
+
+// Serve /metrics on a background thread. Each connection gets its own thread, so a client that connects and
+// sends nothing only holds up itself until the read timeout, not every other scrape
+pub fn serve(addr: SocketAddr, snapshot: MetricsSnapshot) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let snapshot = Arc::clone(&snapshot);
+            thread::spawn(move || handle(stream, &snapshot));
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Read, time::Instant};
+
+    #[test]
+    fn idle_clients_do_not_block_scrapes() {
+        // A free port, released again for serve() to bind
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        serve(addr, Arc::new(Mutex::new("net_monitor_up 1\n".to_string()))).unwrap();
+
+        let _idle = TcpStream::connect(addr).unwrap();
+        let started = Instant::now();
+        let mut scrape = TcpStream::connect(addr).unwrap();
+        scrape.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        scrape.read_to_string(&mut response).unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nnet_monitor_up 1\n"));
+    }
+}
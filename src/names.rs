@@ -0,0 +1,501 @@
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::{Duration, Instant},
+};
+
+// Where a hostname was learned from, in order of preference
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub enum NameSource {
+    Dhcp,
+    Mdns,
+    NetBios,
+    Llmnr,
+    Dns,
+}
+
+impl NameSource {
+    pub fn label(self) -> &'static str {
+        match self {
+            NameSource::Dhcp => "DHCP",
+            NameSource::Mdns => "mDNS",
+            NameSource::NetBios => "NetBIOS",
+            NameSource::Llmnr => "LLMNR",
+            NameSource::Dns => "DNS",
+        }
+    }
+}
+
+// An IP -> name binding seen on the wire
+pub struct NameObservation {
+    pub ip: IpAddr,
+    pub name: String,
+    pub source: NameSource,
+}
+
+// Every name known for one host, latest per source
+#[derive(Default)]
+pub struct HostNames {
+    names: BTreeMap<NameSource, String>,
+    // When a name was last seen on the wire
+    updated: Option<Instant>,
+}
+
+impl HostNames {
+    pub fn insert(&mut self, source: NameSource, name: String) {
+        self.names.insert(source, name);
+        self.updated = Some(Instant::now());
+    }
+
+    // Not seen again for longer than `ttl`
+    pub fn is_stale(&self, ttl: Duration) -> bool {
+        self.updated.is_none_or(|updated| updated.elapsed() > ttl)
+    }
+
+    // Name from the most trustworthy source
+    pub fn best(&self) -> Option<&str> {
+        self.names.values().next().map(String::as_str)
+    }
+
+    // Every known name, most trustworthy source first
+    pub fn iter(&self) -> impl Iterator<Item = (NameSource, &str)> {
+        self.names.iter().map(|(source, name)| (*source, name.as_str()))
+    }
+}
+
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+const DNS_PORT: u16 = 53;
+const NETBIOS_NS_PORT: u16 = 137;
+const MDNS_PORT: u16 = 5353;
+const LLMNR_PORT: u16 = 5355;
+
+// Decode name bindings from a UDP payload, purely from what is on the wire
+pub fn extract(src: IpAddr, src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<NameObservation> {
+    let has_port = |port| src_port == port || dst_port == port;
+    if has_port(DHCP_SERVER_PORT) && has_port(DHCP_CLIENT_PORT) {
+        parse_dhcp(src, payload)
+    } else if has_port(MDNS_PORT) {
+        parse_dns(payload, NameSource::Mdns)
+    } else if src_port == LLMNR_PORT {
+        parse_dns(payload, NameSource::Llmnr)
+    } else if has_port(NETBIOS_NS_PORT) {
+        parse_dns(payload, NameSource::NetBios)
+    } else if src_port == DNS_PORT {
+        parse_dns(payload, NameSource::Dns)
+    } else {
+        Vec::new()
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]))
+}
+
+// DHCP client FQDN option: flags, two obsolete rcodes, then the name, DNS wire encoded when the E flag is set
+fn client_fqdn(value: &[u8]) -> Option<String> {
+    let name = if value.first()? & 0x04 != 0 {
+        read_name(value, 3)?.0
+    } else {
+        String::from_utf8_lossy(value.get(3..)?).trim_end_matches(['\0', '.']).to_string()
+    };
+    Some(name)
+}
+
+// DHCP client messages carry the hostname in option 12, or a fully qualified one in option 81
+fn parse_dhcp(src: IpAddr, payload: &[u8]) -> Vec<NameObservation> {
+    const OPTIONS_OFFSET: usize = 240;
+    const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+    // Only BOOTREQUESTs describe the sending client
+    if payload.len() < OPTIONS_OFFSET || payload[0] != 1 || payload[236..240] != MAGIC_COOKIE {
+        return Vec::new();
+    }
+    let ciaddr = Ipv4Addr::new(payload[12], payload[13], payload[14], payload[15]);
+
+    let mut hostname = None;
+    let mut fqdn = None;
+    let mut requested_ip = None;
+    let mut i = OPTIONS_OFFSET;
+    while i < payload.len() {
+        let code = payload[i];
+        match code {
+            0 => { i += 1; continue; }
+            255 => break,
+            _ => {}
+        }
+        let Some(&len) = payload.get(i + 1) else { break };
+        let Some(value) = payload.get(i + 2..i + 2 + len as usize) else { break };
+        match code {
+            12 => hostname = Some(String::from_utf8_lossy(value).trim_end_matches('\0').to_string()),
+            81 => fqdn = client_fqdn(value),
+            50 if value.len() == 4 => requested_ip = Some(Ipv4Addr::new(value[0], value[1], value[2], value[3])),
+            _ => {}
+        }
+        i += 2 + len as usize;
+    }
+
+    // A renewing client already has an address; a new one announces the address it asks for
+    let ip = if !ciaddr.is_unspecified() {
+        Some(IpAddr::V4(ciaddr))
+    } else if let Some(requested) = requested_ip {
+        Some(IpAddr::V4(requested))
+    } else if !src.is_unspecified() {
+        Some(src)
+    } else {
+        None
+    };
+
+    match (ip, hostname.or(fqdn)) {
+        (Some(ip), Some(name)) if !name.is_empty() => vec![NameObservation { ip, name, source: NameSource::Dhcp }],
+        _ => Vec::new(),
+    }
+}
+
+// Read a possibly compressed domain name, returning it and the offset just past it
+fn read_name(msg: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let len = *msg.get(offset)? as usize;
+        if len == 0 {
+            end.get_or_insert(offset + 1);
+            break;
+        }
+        if len & 0xC0 == 0xC0 {
+            // Compression pointer
+            let pointer = (read_u16(msg, offset)? & 0x3FFF) as usize;
+            end.get_or_insert(offset + 2);
+            jumps += 1;
+            if jumps > 16 {
+                return None;
+            }
+            offset = pointer;
+            continue;
+        }
+        let label = msg.get(offset + 1..offset + 1 + len)?;
+        labels.push(String::from_utf8_lossy(label).to_string());
+        offset += 1 + len;
+    }
+
+    Some((labels.join("."), end?))
+}
+
+// NetBIOS names are "first level encoded": each nibble of the 16-byte name becomes 'A' + nibble
+fn decode_netbios_name(encoded: &str) -> Option<String> {
+    let first_label = encoded.split('.').next()?.as_bytes();
+    if first_label.len() != 32 {
+        return None;
+    }
+    let bytes: Vec<u8> = first_label
+        .chunks(2)
+        .map(|pair| ((pair[0].wrapping_sub(b'A')) << 4) | (pair[1].wrapping_sub(b'A') & 0x0F))
+        .collect();
+    // The 16th byte is the service suffix; only workstation (0x00) and server (0x20) names identify a host
+    if bytes[15] != 0x00 && bytes[15] != 0x20 {
+        return None;
+    }
+    let name = String::from_utf8_lossy(&bytes[..15]).trim_end().to_string();
+    if name.is_empty() || name.starts_with('*') { None } else { Some(name) }
+}
+
+// DNS, mDNS, LLMNR and NetBIOS name service share the same message layout
+fn parse_dns(msg: &[u8], source: NameSource) -> Vec<NameObservation> {
+    const TYPE_A: u16 = 1;
+    const TYPE_CNAME: u16 = 5;
+    const TYPE_NB: u16 = 32;
+    const TYPE_AAAA: u16 = 28;
+
+    let mut found = Vec::new();
+    let (Some(flags), Some(qdcount), Some(ancount), Some(nscount), Some(arcount)) =
+        (read_u16(msg, 2), read_u16(msg, 4), read_u16(msg, 6), read_u16(msg, 8), read_u16(msg, 10))
+    else {
+        return found;
+    };
+    let is_response = flags & 0x8000 != 0;
+    let opcode = (flags >> 11) & 0x0F;
+    // NetBIOS name registrations (opcode 5) and refreshes (8/9) are requests that carry the address
+    let is_registration = source == NameSource::NetBios && matches!(opcode, 5 | 8 | 9);
+    if !is_response && !is_registration {
+        return found;
+    }
+
+    let mut offset = 12;
+    let mut question = None;
+    for _ in 0..qdcount {
+        let Some((name, next)) = read_name(msg, offset) else { return found };
+        question.get_or_insert(name);
+        offset = next + 4;
+    }
+
+    // A DNS response's authority and additional sections describe name servers, not the question. mDNS and LLMNR
+    // records name their own owner, and NetBIOS registrations carry the address in the additional section
+    let records = if source == NameSource::Dns { ancount as u32 } else { ancount as u32 + nscount as u32 + arcount as u32 };
+    // The question and every CNAME target reached from it
+    let mut aliases: Vec<String> = question.iter().cloned().collect();
+    let is_alias = |aliases: &[String], name: &str| aliases.iter().any(|alias| alias.eq_ignore_ascii_case(name));
+
+    for _ in 0..records {
+        let Some((owner, next)) = read_name(msg, offset) else { break };
+        let (Some(rtype), Some(rdlength)) = (read_u16(msg, next), read_u16(msg, next + 8)) else { break };
+        let rdata_start = next + 10;
+        let Some(rdata) = msg.get(rdata_start..rdata_start + rdlength as usize) else { break };
+        offset = rdata_start + rdlength as usize;
+
+        if source == NameSource::Dns && rtype == TYPE_CNAME && is_alias(&aliases, &owner) {
+            if let Some((target, _)) = read_name(msg, rdata_start) {
+                aliases.push(target);
+            }
+            continue;
+        }
+
+        let ip = match (source, rtype) {
+            (NameSource::NetBios, TYPE_NB) if rdata.len() >= 6 => {
+                Some(IpAddr::V4(Ipv4Addr::new(rdata[2], rdata[3], rdata[4], rdata[5])))
+            }
+            (NameSource::NetBios, _) => None,
+            (_, TYPE_A) if rdata.len() == 4 => Some(IpAddr::V4(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]))),
+            (_, TYPE_AAAA) if rdata.len() == 16 => {
+                let octets: [u8; 16] = rdata.try_into().unwrap();
+                Some(IpAddr::V6(Ipv6Addr::from(octets)))
+            }
+            _ => None,
+        };
+        let Some(ip) = ip else { continue };
+
+        let name = match source {
+            NameSource::NetBios => decode_netbios_name(&owner),
+            // Answers to a lookup may go through CNAMEs; the name the client asked for is the useful one
+            NameSource::Dns if is_alias(&aliases, &owner) => question.clone(),
+            _ => Some(owner),
+        };
+        if let Some(name) = name.filter(|n| !n.is_empty()) {
+            found.push(NameObservation { ip, name, source });
+        }
+    }
+
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10));
+    const RESPONSE: u16 = 0x8180;
+
+    // Uncompressed wire encoding of a dotted name
+    fn name(dotted: &str) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for label in dotted.split('.').filter(|l| !l.is_empty()) {
+            encoded.push(label.len() as u8);
+            encoded.extend_from_slice(label.as_bytes());
+        }
+        encoded.push(0);
+        encoded
+    }
+
+    // Resource record with an already encoded owner name
+    fn record(owner: &[u8], rtype: u16, rdata: &[u8]) -> Vec<u8> {
+        let mut rr = owner.to_vec();
+        rr.extend_from_slice(&rtype.to_be_bytes());
+        rr.extend_from_slice(&[0, 1, 0, 0, 0, 60]); // class IN, TTL
+        rr.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        rr.extend_from_slice(rdata);
+        rr
+    }
+
+    // Header, questions of type A, then the answer, authority and additional sections
+    fn message(flags: u16, questions: &[&str], sections: [&[Vec<u8>]; 3]) -> Vec<u8> {
+        let mut msg = vec![0x12, 0x34];
+        msg.extend_from_slice(&flags.to_be_bytes());
+        msg.extend_from_slice(&(questions.len() as u16).to_be_bytes());
+        for section in sections {
+            msg.extend_from_slice(&(section.len() as u16).to_be_bytes());
+        }
+        for question in questions {
+            msg.extend(name(question));
+            msg.extend_from_slice(&[0, 1, 0, 1]);
+        }
+        for rr in sections.iter().flat_map(|section| section.iter()) {
+            msg.extend_from_slice(rr);
+        }
+        msg
+    }
+
+    // First level encoding of a NetBIOS name with its service suffix
+    fn netbios(plain: &str, suffix: u8) -> Vec<u8> {
+        let mut raw = format!("{:<15}", plain).into_bytes();
+        raw.push(suffix);
+        let encoded: Vec<u8> = raw.iter().flat_map(|b| [b'A' + (b >> 4), b'A' + (b & 0x0f)]).collect();
+        name(std::str::from_utf8(&encoded).unwrap())
+    }
+
+    fn names(found: &[NameObservation]) -> Vec<(IpAddr, &str, NameSource)> {
+        found.iter().map(|o| (o.ip, o.name.as_str(), o.source)).collect()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn compressed_names() {
+        // "www" followed by a pointer to "example.com" at offset 3
+        let msg = [&[3, b'x', b'y', b'z'][..], &name("example.com"), &[3, b'w', b'w', b'w', 0xc0, 4]].concat();
+        assert_eq!(read_name(&msg, 0), Some(("xyz.example.com".to_string(), 17)));
+        // The end is just past the pointer, not past the name it points to
+        assert_eq!(read_name(&msg, 17), Some(("www.example.com".to_string(), 23)));
+
+        // Pointers to themselves, to each other and out of the message
+        assert_eq!(read_name(&[0xc0, 0], 0), None);
+        assert_eq!(read_name(&[0xc0, 2, 0xc0, 0], 0), None);
+        assert_eq!(read_name(&[0xc0, 9], 0), None);
+        // A label longer than what is left
+        assert_eq!(read_name(&[5, b'a', b'b'], 0), None);
+    }
+
+    #[test]
+    fn dns_answers_follow_cnames_back_to_the_question() {
+        let answers = [
+            record(&[0xc0, 12], 5, &name("cdn.example.net")),
+            record(&name("cdn.example.net"), 5, &name("edge.example.org")),
+            record(&name("edge.example.org"), 1, &[93, 184, 216, 34]),
+            record(&name("edge.example.org"), 28, &"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets()),
+        ];
+        let msg = message(RESPONSE, &["www.example.com"], [&answers, &[], &[]]);
+        assert_eq!(
+            names(&extract(ip("8.8.8.8"), 53, 40000, &msg)),
+            [
+                (ip("93.184.216.34"), "www.example.com", NameSource::Dns),
+                (ip("2001:db8::1"), "www.example.com", NameSource::Dns),
+            ]
+        );
+
+        // A CNAME loop back to the question still names the address after the question
+        let answers = [
+            record(&name("a.example.com"), 5, &name("b.example.com")),
+            record(&name("b.example.com"), 5, &name("A.EXAMPLE.COM")),
+            record(&name("b.example.com"), 1, &[10, 0, 0, 1]),
+        ];
+        let msg = message(RESPONSE, &["a.example.com"], [&answers, &[], &[]]);
+        assert_eq!(names(&parse_dns(&msg, NameSource::Dns)), [(ip("10.0.0.1"), "a.example.com", NameSource::Dns)]);
+    }
+
+    #[test]
+    fn dns_ignores_queries_and_glue() {
+        let answer = [record(&[0xc0, 12], 1, &[10, 0, 0, 1])];
+        let query = message(0x0100, &["host.example.com"], [&answer, &[], &[]]);
+        assert!(parse_dns(&query, NameSource::Dns).is_empty());
+
+        // Name server addresses in the additional section, and an unrelated owner in the answers
+        let answers = [record(&name("other.example.com"), 1, &[10, 0, 0, 2])];
+        let authority = [record(&name("example.com"), 2, &name("ns1.example.com"))];
+        let glue = [record(&name("ns1.example.com"), 1, &[10, 0, 0, 53])];
+        let msg = message(RESPONSE, &["host.example.com"], [&answers, &authority, &glue]);
+        assert_eq!(names(&parse_dns(&msg, NameSource::Dns)), [(ip("10.0.0.2"), "other.example.com", NameSource::Dns)]);
+    }
+
+    #[test]
+    fn mdns_and_llmnr_name_the_record_owner() {
+        let announcement = [record(&name("printer.local"), 1, &[192, 168, 1, 50])];
+        let additional = [record(&name("nas.local"), 28, &"fe80::1".parse::<Ipv6Addr>().unwrap().octets())];
+        let msg = message(0x8400, &[], [&announcement, &[], &additional]);
+        assert_eq!(
+            names(&extract(ip("192.168.1.50"), 5353, 5353, &msg)),
+            [(ip("192.168.1.50"), "printer.local", NameSource::Mdns), (ip("fe80::1"), "nas.local", NameSource::Mdns)]
+        );
+
+        let answer = [record(&[0xc0, 12], 1, &[192, 168, 1, 20])];
+        let msg = message(0x8000, &["desktop"], [&answer, &[], &[]]);
+        assert_eq!(names(&extract(ip("192.168.1.20"), 5355, 50000, &msg)), [(ip("192.168.1.20"), "desktop", NameSource::Llmnr)]);
+        // Only the responder's packets count, not the multicast query
+        assert!(extract(ip("192.168.1.30"), 50000, 5355, &msg).is_empty());
+    }
+
+    #[test]
+    fn netbios_registrations_and_responses() {
+        assert_eq!(decode_netbios_name(std::str::from_utf8(&netbios("DESKTOP-01", 0x20)[1..33]).unwrap()), Some("DESKTOP-01".to_string()));
+        // Group and browser names do not identify a host
+        assert_eq!(decode_netbios_name(std::str::from_utf8(&netbios("WORKGROUP", 0x1e)[1..33]).unwrap()), None);
+        assert_eq!(decode_netbios_name("ABCD"), None);
+
+        // Registration request (opcode 5): the address is in the additional section, owned by the question name
+        let mut question_msg = vec![0x12, 0x34, 0x29, 0x10, 0, 1, 0, 0, 0, 0, 0, 1];
+        question_msg.extend(netbios("DESKTOP-01", 0x00));
+        question_msg.extend_from_slice(&[0, 32, 0, 1]);
+        question_msg.extend(record(&[0xc0, 12], 32, &[0x60, 0, 192, 168, 1, 10]));
+        assert_eq!(
+            names(&extract(CLIENT, 137, 137, &question_msg)),
+            [(CLIENT, "DESKTOP-01", NameSource::NetBios)]
+        );
+
+        // A name query is neither a response nor a registration
+        question_msg[2] = 0x01;
+        assert!(extract(CLIENT, 137, 137, &question_msg).is_empty());
+    }
+
+    #[test]
+    fn truncated_and_looping_messages_yield_nothing() {
+        let answers = [record(&[0xc0, 12], 1, &[10, 0, 0, 1])];
+        let msg = message(RESPONSE, &["host.example.com"], [&answers, &[], &[]]);
+        assert_eq!(parse_dns(&msg, NameSource::Dns).len(), 1);
+        // The address is the last thing in the message, so every shorter cut must drop it
+        for len in 0..msg.len() {
+            assert!(parse_dns(&msg[..len], NameSource::Dns).is_empty(), "cut at {}", len);
+        }
+
+        // An answer whose owner points at itself, and a record count larger than the message
+        let mut looping = message(RESPONSE, &["host.example.com"], [&[], &[], &[]]);
+        looping[7] = 1;
+        let at = looping.len() as u8;
+        looping.extend(record(&[0xc0, at], 1, &[10, 0, 0, 1]));
+        assert!(parse_dns(&looping, NameSource::Dns).is_empty());
+        looping[6] = 0xff;
+        assert!(parse_dns(&looping, NameSource::Mdns).is_empty());
+    }
+
+    // DHCP request from 0.0.0.0 with the given options after the magic cookie
+    fn dhcp(ciaddr: [u8; 4], options: &[u8]) -> Vec<u8> {
+        let mut msg = vec![0; 240];
+        msg[0] = 1; // BOOTREQUEST
+        msg[12..16].copy_from_slice(&ciaddr);
+        msg[236..240].copy_from_slice(&[99, 130, 83, 99]);
+        msg.extend_from_slice(options);
+        msg.push(255);
+        msg
+    }
+
+    #[test]
+    fn dhcp_hostname_options() {
+        let unspecified = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+        let requested = [50, 4, 192, 168, 1, 10];
+        let hostname = [&[12, 6][..], b"laptop"].concat();
+        let wire_fqdn = [&[81, 23, 0x04, 0, 0][..], &name("laptop.example.com")].concat();
+        let ascii_fqdn = [&[81, 21, 0x00, 0, 0][..], b"laptop.example.com."].concat();
+
+        // Option 12 wins over option 81, whichever comes first
+        let msg = dhcp([0; 4], &[&wire_fqdn[..], &requested, &hostname].concat());
+        assert_eq!(names(&extract(unspecified, 68, 67, &msg)), [(CLIENT, "laptop", NameSource::Dhcp)]);
+
+        // Otherwise the FQDN, wire or ASCII encoded
+        for fqdn in [&wire_fqdn, &ascii_fqdn] {
+            let msg = dhcp([0; 4], &[&requested[..], fqdn].concat());
+            assert_eq!(names(&extract(unspecified, 68, 67, &msg)), [(CLIENT, "laptop.example.com", NameSource::Dhcp)]);
+        }
+
+        // A renewing client is named by ciaddr; a client with no address at all is skipped
+        let msg = dhcp([192, 168, 1, 99], &hostname);
+        assert_eq!(names(&extract(CLIENT, 68, 67, &msg))[0].0, ip("192.168.1.99"));
+        assert!(extract(unspecified, 68, 67, &dhcp([0; 4], &hostname)).is_empty());
+
+        // Server replies, and an option running past the end of the packet
+        let mut reply = dhcp([0; 4], &hostname);
+        reply[0] = 2;
+        assert!(extract(ip("192.168.1.1"), 67, 68, &reply).is_empty());
+        let truncated = dhcp([0; 4], &[12, 200, b'x']);
+        assert!(extract(CLIENT, 68, 67, &truncated[..truncated.len() - 1]).is_empty());
+    }
+}
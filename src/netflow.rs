@@ -0,0 +1,484 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    str::FromStr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::constants::MAX_FLOWS;
+use crate::flow::{FlowDelta, FlowKey};
+
+// NetFlow v5 caps a datagram at 30 records; v9/IPFIX sets are kept to a similar size
+const MAX_V5_RECORDS: usize = 30;
+const MAX_RECORDS_PER_SET: usize = 20;
+// Templates are resent periodically since UDP collectors may have missed them
+const TEMPLATE_REFRESH: Duration = Duration::from_secs(60);
+
+const TEMPLATE_ID_V4: u16 = 256;
+const TEMPLATE_ID_V6: u16 = 257;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FlowVersion {
+    V5,
+    V9,
+    Ipfix,
+}
+
+impl FromStr for FlowVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "5" | "v5" => Ok(FlowVersion::V5),
+            "9" | "v9" => Ok(FlowVersion::V9),
+            "10" | "ipfix" => Ok(FlowVersion::Ipfix),
+            _ => Err(format!("unknown flow export version '{}', expected 5, 9 or ipfix", s)),
+        }
+    }
+}
+
+// Counters of a flow since it was last exported
+struct FlowRecord {
+    bytes: u64,
+    packets: u64,
+    first: SystemTime,
+    last: SystemTime,
+}
+
+// Acts as a flow probe: keeps its own cache with active/inactive timeouts and emits records over UDP
+pub struct FlowExporter {
+    socket: UdpSocket,
+    collector: SocketAddr,
+    version: FlowVersion,
+    active_timeout: Duration,
+    inactive_timeout: Duration,
+    cache: HashMap<FlowKey, FlowRecord>,
+    // Reference point for the sysUptime based timestamps of v5/v9
+    boot: Option<SystemTime>,
+    // v5 and IPFIX count records, v9 counts datagrams
+    sequence: u32,
+    last_template: Option<Instant>,
+}
+
+impl FlowExporter {
+    pub fn new(
+        collector: SocketAddr,
+        version: FlowVersion,
+        active_timeout: Duration,
+        inactive_timeout: Duration,
+    ) -> io::Result<Self> {
+        let bind: SocketAddr = if collector.is_ipv4() { "0.0.0.0:0".parse().unwrap() } else { "[::]:0".parse().unwrap() };
+        Ok(Self {
+            socket: UdpSocket::bind(bind)?,
+            collector,
+            version,
+            active_timeout,
+            inactive_timeout,
+            cache: HashMap::new(),
+            boot: None,
+            sequence: 0,
+            last_template: None,
+        })
+    }
+
+    // Fold one tick worth of flow deltas into the export cache
+    pub fn observe(&mut self, deltas: &HashMap<FlowKey, FlowDelta>) {
+        for (key, delta) in deltas {
+            // NetFlow v5 only describes IPv4 flows
+            if self.version == FlowVersion::V5 && !key.src.is_ipv4() {
+                continue;
+            }
+            self.boot = Some(self.boot.map_or(delta.first_seen, |b| b.min(delta.first_seen)));
+            let record = self.cache.entry(*key).or_insert(FlowRecord {
+                bytes: 0,
+                packets: 0,
+                first: delta.first_seen,
+                last: delta.last_seen,
+            });
+            record.bytes += delta.bytes;
+            record.packets += delta.packets;
+            record.first = record.first.min(delta.first_seen);
+            record.last = record.last.max(delta.last_seen);
+        }
+    }
+
+    // Export flows that went idle (inactive timeout) or have been running too long (active timeout)
+    pub fn expire(&mut self, now: SystemTime) -> io::Result<()> {
+        let (active, inactive) = (self.active_timeout, self.inactive_timeout);
+        let expired: Vec<FlowKey> = self.cache.iter()
+            .filter(|(_, r)| {
+                now.duration_since(r.last).unwrap_or_default() >= inactive
+                    || r.last.duration_since(r.first).unwrap_or_default() >= active
+            })
+            .map(|(key, _)| *key)
+            .collect();
+        let records: Vec<(FlowKey, FlowRecord)> = expired.into_iter()
+            .filter_map(|key| self.cache.remove(&key).map(|r| (key, r)))
+            .collect();
+        self.export(records, now)
+    }
+
+    // Export everything still cached, e.g. on shutdown
+    pub fn flush(&mut self, now: SystemTime) -> io::Result<()> {
+        let records: Vec<(FlowKey, FlowRecord)> = self.cache.drain().collect();
+        self.export(records, now)
+    }
+
+    // Send the records one datagram at a time. When a send fails, that datagram's records and all later ones
+    // go back into the cache, so they are exported again on the next tick
+    fn export(&mut self, records: Vec<(FlowKey, FlowRecord)>, now: SystemTime) -> io::Result<()> {
+        let datagrams = match self.version {
+            FlowVersion::V5 => batches(records, MAX_V5_RECORDS),
+            // Every data set uses a single template, so IPv4 and IPv6 go in separate datagrams
+            FlowVersion::V9 | FlowVersion::Ipfix => {
+                let (v4, v6): (Vec<_>, Vec<_>) = records.into_iter().partition(|(key, _)| key.src.is_ipv4());
+                let mut datagrams = batches(v4, MAX_RECORDS_PER_SET);
+                datagrams.extend(batches(v6, MAX_RECORDS_PER_SET));
+                datagrams
+            }
+        };
+        let mut datagrams = datagrams.into_iter();
+        while let Some(batch) = datagrams.next() {
+            let (sequence, last_template) = (self.sequence, self.last_template);
+            let datagram = match self.version {
+                FlowVersion::V5 => self.encode_v5(&batch, now),
+                FlowVersion::V9 | FlowVersion::Ipfix => {
+                    let template_id = if batch[0].0.src.is_ipv4() { TEMPLATE_ID_V4 } else { TEMPLATE_ID_V6 };
+                    self.encode_templated(template_id, &batch, now)
+                }
+            };
+            if let Err(e) = self.socket.send_to(&datagram, self.collector) {
+                // The collector never saw this datagram, so its sequence number and templates are still due
+                self.sequence = sequence;
+                self.last_template = last_template;
+                for (key, record) in batch.into_iter().chain(datagrams.flatten()) {
+                    self.requeue(key, record);
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    // Put back a record that could not be sent, merging it with anything observed since. While the collector
+    // stays unreachable the cache would otherwise keep growing, so past the flow table's limit the record is lost
+    fn requeue(&mut self, key: FlowKey, record: FlowRecord) {
+        let full = self.cache.len() >= MAX_FLOWS;
+        match self.cache.entry(key) {
+            Entry::Occupied(mut entry) => {
+                let cached = entry.get_mut();
+                cached.bytes += record.bytes;
+                cached.packets += record.packets;
+                cached.first = cached.first.min(record.first);
+                cached.last = cached.last.max(record.last);
+            }
+            Entry::Vacant(_) if full => {}
+            Entry::Vacant(entry) => {
+                entry.insert(record);
+            }
+        }
+    }
+
+    // Milliseconds since the exporter's reference point, as used by v5/v9 timestamps
+    fn uptime_ms(&self, t: SystemTime) -> u32 {
+        let boot = self.boot.unwrap_or(t);
+        t.duration_since(boot).unwrap_or_default().as_millis() as u32
+    }
+
+    fn encode_v5(&mut self, records: &[(FlowKey, FlowRecord)], now: SystemTime) -> Vec<u8> {
+        let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut buf = Vec::with_capacity(24 + records.len() * 48);
+        put_u16(&mut buf, 5);
+        put_u16(&mut buf, records.len() as u16);
+        put_u32(&mut buf, self.uptime_ms(now));
+        put_u32(&mut buf, since_epoch.as_secs() as u32);
+        put_u32(&mut buf, since_epoch.subsec_nanos());
+        put_u32(&mut buf, self.sequence);
+        buf.extend_from_slice(&[0, 0]); // engine type, engine id
+        put_u16(&mut buf, 0); // sampling interval
+
+        for (key, record) in records {
+            let (IpAddr::V4(src), IpAddr::V4(dst)) = (key.src, key.dst) else { continue };
+            buf.extend_from_slice(&src.octets());
+            buf.extend_from_slice(&dst.octets());
+            buf.extend_from_slice(&[0; 4]); // next hop
+            put_u16(&mut buf, 0); // input ifIndex
+            put_u16(&mut buf, 0); // output ifIndex
+            put_u32(&mut buf, record.packets.min(u32::MAX as u64) as u32);
+            put_u32(&mut buf, record.bytes.min(u32::MAX as u64) as u32);
+            put_u32(&mut buf, self.uptime_ms(record.first));
+            put_u32(&mut buf, self.uptime_ms(record.last));
+            put_u16(&mut buf, key.src_port);
+            put_u16(&mut buf, key.dst_port);
+            buf.extend_from_slice(&[0, 0, key.protocol, 0]); // pad, tcp flags, protocol, tos
+            buf.extend_from_slice(&[0; 8]); // src/dst AS, src/dst mask, pad
+        }
+        self.sequence = self.sequence.wrapping_add(records.len() as u32);
+        buf
+    }
+
+    // Template fields as (information element id, length)
+    fn template_fields(&self, template_id: u16) -> Vec<(u16, u16)> {
+        let (src, dst, addr_len) = if template_id == TEMPLATE_ID_V4 { (8, 12, 4) } else { (27, 28, 16) };
+        let mut fields = vec![(src, addr_len), (dst, addr_len), (7, 2), (11, 2), (4, 1), (1, 8), (2, 8)];
+        match self.version {
+            // flowStartMilliseconds / flowEndMilliseconds, absolute
+            FlowVersion::Ipfix => fields.extend([(152, 8), (153, 8)]),
+            // FIRST_SWITCHED / LAST_SWITCHED, relative to sysUptime
+            _ => fields.extend([(22, 4), (21, 4)]),
+        }
+        fields
+    }
+
+    // NetFlow v9 and IPFIX share the template + data set layout, only the headers differ
+    fn encode_templated(&mut self, template_id: u16, records: &[(FlowKey, FlowRecord)], now: SystemTime) -> Vec<u8> {
+        let ipfix = self.version == FlowVersion::Ipfix;
+        let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut buf = Vec::new();
+        let mut record_count = records.len() as u16;
+
+        // Header, lengths/counts patched below
+        if ipfix {
+            put_u16(&mut buf, 10);
+            put_u16(&mut buf, 0); // total length
+            put_u32(&mut buf, since_epoch.as_secs() as u32);
+            put_u32(&mut buf, self.sequence);
+            put_u32(&mut buf, 0); // observation domain
+        } else {
+            put_u16(&mut buf, 9);
+            put_u16(&mut buf, 0); // record count
+            put_u32(&mut buf, self.uptime_ms(now));
+            put_u32(&mut buf, since_epoch.as_secs() as u32);
+            put_u32(&mut buf, self.sequence);
+            put_u32(&mut buf, 0); // source id
+        }
+
+        let send_templates = self.last_template.is_none_or(|t| t.elapsed() >= TEMPLATE_REFRESH);
+        if send_templates {
+            let set_start = buf.len();
+            put_u16(&mut buf, if ipfix { 2 } else { 0 });
+            put_u16(&mut buf, 0);
+            for id in [TEMPLATE_ID_V4, TEMPLATE_ID_V6] {
+                let fields = self.template_fields(id);
+                put_u16(&mut buf, id);
+                put_u16(&mut buf, fields.len() as u16);
+                for (element, len) in fields {
+                    put_u16(&mut buf, element);
+                    put_u16(&mut buf, len);
+                }
+                record_count += 1;
+            }
+            patch_len(&mut buf, set_start);
+            self.last_template = Some(Instant::now());
+        }
+
+        let set_start = buf.len();
+        put_u16(&mut buf, template_id);
+        put_u16(&mut buf, 0);
+        for (key, record) in records {
+            match (key.src, key.dst) {
+                (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                    buf.extend_from_slice(&src.octets());
+                    buf.extend_from_slice(&dst.octets());
+                }
+                (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                    buf.extend_from_slice(&src.octets());
+                    buf.extend_from_slice(&dst.octets());
+                }
+                _ => continue,
+            }
+            put_u16(&mut buf, key.src_port);
+            put_u16(&mut buf, key.dst_port);
+            buf.push(key.protocol);
+            buf.extend_from_slice(&record.bytes.to_be_bytes());
+            buf.extend_from_slice(&record.packets.to_be_bytes());
+            if ipfix {
+                let ms = |t: SystemTime| t.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+                buf.extend_from_slice(&ms(record.first).to_be_bytes());
+                buf.extend_from_slice(&ms(record.last).to_be_bytes());
+            } else {
+                put_u32(&mut buf, self.uptime_ms(record.first));
+                put_u32(&mut buf, self.uptime_ms(record.last));
+            }
+        }
+        // Sets are padded to a 4-byte boundary
+        while (buf.len() - set_start) % 4 != 0 {
+            buf.push(0);
+        }
+        patch_len(&mut buf, set_start);
+
+        if ipfix {
+            let total = buf.len() as u16;
+            buf[2..4].copy_from_slice(&total.to_be_bytes());
+            self.sequence = self.sequence.wrapping_add(records.len() as u32);
+        } else {
+            buf[2..4].copy_from_slice(&record_count.to_be_bytes());
+            self.sequence = self.sequence.wrapping_add(1);
+        }
+        buf
+    }
+}
+
+// Split records into groups of at most `size`, one per datagram
+fn batches<T>(records: Vec<T>, size: usize) -> Vec<Vec<T>> {
+    let mut records = records.into_iter().peekable();
+    let mut batches = Vec::new();
+    while records.peek().is_some() {
+        batches.push(records.by_ref().take(size).collect());
+    }
+    batches
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+// Write the length of the set starting at `start` into its header
+fn patch_len(buf: &mut [u8], start: usize) {
+    let len = (buf.len() - start) as u16;
+    buf[start + 2..start + 4].copy_from_slice(&len.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const SRC: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 10);
+    const DST: Ipv4Addr = Ipv4Addr::new(8, 8, 8, 8);
+
+    fn key() -> FlowKey {
+        FlowKey { protocol: 17, src: IpAddr::V4(SRC), src_port: 40000, dst: IpAddr::V4(DST), dst_port: 53 }
+    }
+
+    fn be16(data: &[u8], offset: usize) -> u16 {
+        u16::from_be_bytes([data[offset], data[offset + 1]])
+    }
+
+    fn be32(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn be64(data: &[u8], offset: usize) -> u64 {
+        u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+    }
+
+    // Expire one idle flow of 3 packets and 1500 bytes and return the datagram a local collector received
+    fn export_one(version: FlowVersion) -> Vec<u8> {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut exporter =
+            FlowExporter::new(collector.local_addr().unwrap(), version, Duration::from_secs(1800), Duration::from_secs(15)).unwrap();
+
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut delta = FlowDelta::new(start);
+        delta.add(500, start);
+        delta.add(500, start + Duration::from_millis(500));
+        delta.add(500, start + Duration::from_secs(1));
+        exporter.observe(&HashMap::from([(key(), delta)]));
+
+        // Still active
+        exporter.expire(start + Duration::from_secs(10)).unwrap();
+        assert_eq!(exporter.cache.len(), 1);
+        exporter.expire(start + Duration::from_secs(20)).unwrap();
+        assert!(exporter.cache.is_empty());
+
+        let mut buf = [0; 1500];
+        let len = collector.recv(&mut buf).unwrap();
+        buf[..len].to_vec()
+    }
+
+    // (set id, set body) of every set after a v9 or IPFIX header
+    fn sets(datagram: &[u8], header_len: usize) -> Vec<(u16, &[u8])> {
+        let mut sets = Vec::new();
+        let mut rest = &datagram[header_len..];
+        while !rest.is_empty() {
+            let len = be16(rest, 2) as usize;
+            assert!(len >= 4 && len.is_multiple_of(4) && len <= rest.len());
+            sets.push((be16(rest, 0), &rest[4..len]));
+            rest = &rest[len..];
+        }
+        sets
+    }
+
+    // The IPv4 template: id 256 with 9 fields, starting with the source and destination addresses
+    fn check_template(set: &[u8], ipfix: bool) {
+        assert_eq!((be16(set, 0), be16(set, 2)), (TEMPLATE_ID_V4, 9));
+        assert_eq!((be16(set, 4), be16(set, 6), be16(set, 8), be16(set, 10)), (8, 4, 12, 4));
+        let first_switched = if ipfix { 152 } else { 22 };
+        assert_eq!(be16(set, 4 + 7 * 4), first_switched);
+    }
+
+    // src, dst, ports, protocol, bytes and packets; returns what follows them
+    fn check_data(record: &[u8]) -> &[u8] {
+        assert_eq!(&record[0..8], [SRC.octets(), DST.octets()].concat());
+        assert_eq!((be16(record, 8), be16(record, 10), record[12]), (40000, 53, 17));
+        assert_eq!((be64(record, 13), be64(record, 21)), (1500, 3));
+        &record[29..]
+    }
+
+    #[test]
+    fn v5_header_and_record() {
+        let datagram = export_one(FlowVersion::V5);
+        assert_eq!(datagram.len(), 24 + 48);
+        assert_eq!((be16(&datagram, 0), be16(&datagram, 2)), (5, 1));
+        assert_eq!(be32(&datagram, 8), 1_700_000_020);
+        assert_eq!(be32(&datagram, 16), 0); // sequence
+
+        let record = &datagram[24..];
+        assert_eq!(&record[0..8], [SRC.octets(), DST.octets()].concat());
+        assert_eq!((be32(record, 16), be32(record, 20)), (3, 1500));
+        // First and last packet, in milliseconds of uptime since the first flow was seen
+        assert_eq!((be32(record, 24), be32(record, 28)), (0, 1000));
+        assert_eq!((be16(record, 32), be16(record, 34), record[38]), (40000, 53, 17));
+    }
+
+    #[test]
+    fn v9_template_then_data() {
+        let datagram = export_one(FlowVersion::V9);
+        // Two templates and one data record
+        assert_eq!((be16(&datagram, 0), be16(&datagram, 2)), (9, 3));
+        assert_eq!(be32(&datagram, 16), 0); // source id
+
+        let sets = sets(&datagram, 20);
+        assert_eq!(sets.iter().map(|(id, _)| *id).collect::<Vec<_>>(), [0, TEMPLATE_ID_V4]);
+        check_template(sets[0].1, false);
+        let rest = check_data(sets[1].1);
+        assert_eq!((be32(rest, 0), be32(rest, 4)), (0, 1000));
+    }
+
+    #[test]
+    fn ipfix_template_then_data() {
+        let datagram = export_one(FlowVersion::Ipfix);
+        assert_eq!((be16(&datagram, 0), be16(&datagram, 2) as usize), (10, datagram.len()));
+        assert_eq!(be32(&datagram, 4), 1_700_000_020);
+
+        let sets = sets(&datagram, 16);
+        assert_eq!(sets.iter().map(|(id, _)| *id).collect::<Vec<_>>(), [2, TEMPLATE_ID_V4]);
+        check_template(sets[0].1, true);
+        let rest = check_data(sets[1].1);
+        assert_eq!((be64(rest, 0), be64(rest, 8)), (1_700_000_000_000, 1_700_000_001_000));
+    }
+
+    #[test]
+    fn requeued_records_stop_at_the_flow_limit() {
+        let mut exporter =
+            FlowExporter::new("127.0.0.1:2055".parse().unwrap(), FlowVersion::V9, Duration::from_secs(60), Duration::from_secs(15)).unwrap();
+        let record = |bytes| FlowRecord { bytes, packets: 1, first: UNIX_EPOCH, last: UNIX_EPOCH };
+        for port in 0..MAX_FLOWS {
+            exporter.requeue(FlowKey { src_port: port as u16, dst_port: (port >> 16) as u16, ..key() }, record(1));
+        }
+        assert_eq!(exporter.cache.len(), MAX_FLOWS);
+
+        // A flow that is still cached keeps its counters, a new one no longer fits
+        exporter.requeue(FlowKey { src_port: 0, dst_port: 0, ..key() }, record(10));
+        exporter.requeue(FlowKey { protocol: 6, ..key() }, record(10));
+        assert_eq!(exporter.cache.len(), MAX_FLOWS);
+        assert_eq!(exporter.cache[&FlowKey { src_port: 0, dst_port: 0, ..key() }].bytes, 11);
+    }
+}
@@ -1,29 +1,101 @@
 use std::{
     error::Error,
-    net::Ipv4Addr,
-    sync::{Arc, Mutex},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
+    str::FromStr,
+    mem,
+    sync::mpsc::{self, Receiver, SyncSender, TrySendError},
     thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use pcap::{Capture, Device};
+use pcap::{Capture, Device, Linktype};
 use pnet::datalink;
 use pnet::packet::{
-    ethernet::{EtherTypes, EthernetPacket},
+    ethernet::{EtherType, EtherTypes, EthernetPacket},
+    ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
     ipv4::Ipv4Packet,
-    Packet,
+    ipv6::Ipv6Packet,
+    tcp::TcpPacket,
+    udp::UdpPacket,
 };
-use crate::app::SharedStats;
-use pnet::ipnetwork::Ipv4Network; 
-
-pub fn get_local_ip(device_name: &str) -> Option<Ipv4Addr> {
-    let interfaces = datalink::interfaces();
-    let iface = interfaces.into_iter().find(|i| i.name == device_name)?;
-    iface.ips.iter().find_map(|ip| {
-        if let pnet::ipnetwork::IpNetwork::V4(net) = ip {
-            Some(net.ip())
-        } else {
-            None
+use crate::app::{CaptureStats, StatsBatch};
+use crate::flow::{FlowDelta, FlowKey};
+use crate::names;
+use crate::segment::Segment;
+use pnet::ipnetwork::IpNetwork;
+use pnet::util::MacAddr;
+
+// Addresses of the capturing interface, used to tell which way a packet went
+#[derive(Clone, Default)]
+pub struct LocalAddrs {
+    pub ips: Vec<IpAddr>,
+    pub macs: Vec<MacAddr>,
+}
+
+impl LocalAddrs {
+    // Every IPv4/IPv6 address and the MAC of the interface; all interfaces for Linux's "any" device
+    pub fn of(device_name: &str) -> Self {
+        let mut addrs = LocalAddrs::default();
+        for iface in datalink::interfaces().into_iter().filter(|i| device_name == "any" || i.name == device_name) {
+            addrs.ips.extend(iface.ips.iter().map(|net| net.ip()));
+            addrs.macs.extend(iface.mac.filter(|mac| *mac != MacAddr::zero()));
         }
-    })
+        addrs
+    }
+
+    // Nothing is known about the capturing host, as when reading a capture file
+    pub fn is_empty(&self) -> bool {
+        self.ips.is_empty() && self.macs.is_empty()
+    }
+
+    // Which way a packet went relative to this host, by IP address first and by the link layer otherwise
+    fn direction(&self, src: &IpAddr, dst: &IpAddr, frame: &LinkFrame) -> Direction {
+        if self.ips.contains(src) {
+            return Direction::Tx;
+        }
+        // Broadcasts and multicasts, including subnet-directed ones such as 192.168.1.255, reach every host
+        let group_frame = frame.link_macs.is_some_and(|(_, dst_mac)| dst_mac.0 & 1 == 1);
+        if self.ips.contains(dst) || is_broadcast_or_multicast(dst) || group_frame {
+            return Direction::Rx;
+        }
+        let sent = frame.outgoing.or_else(|| {
+            let (src_mac, dst_mac) = frame.link_macs?;
+            if self.macs.contains(&src_mac) {
+                Some(true)
+            } else if self.macs.contains(&dst_mac) {
+                Some(false)
+            } else {
+                None
+            }
+        });
+        match sent {
+            // Through our interface but for someone else's addresses: routed, bridged or arpspoofed traffic
+            Some(_) if !self.ips.is_empty() => Direction::Forwarded,
+            // An interface without addresses only has the link layer to go by
+            Some(true) => Direction::Tx,
+            Some(false) => Direction::Rx,
+            // Offline there is no "us", so everything counts as received, as it always has
+            None if self.is_empty() => Direction::Rx,
+            // Between two other hosts, seen in promiscuous mode or on a mirror port
+            None => Direction::Forwarded,
+        }
+    }
+}
+
+// Which way a packet crossed the capturing interface
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Rx,
+    Tx,
+    // Neither from nor to this host
+    Forwarded,
+}
+
+fn is_broadcast_or_multicast(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => v4.is_broadcast() || v4.is_multicast(),
+        IpAddr::V6(v6) => v6.is_multicast(),
+    }
 }
 
 pub fn is_rfc1918_private(ip: &Ipv4Addr) -> bool {
@@ -33,64 +105,985 @@ pub fn is_rfc1918_private(ip: &Ipv4Addr) -> bool {
     (octets[0] == 172 && octets[1] >= 16 && octets[1] <= 31)
 }
 
-pub fn get_default_device() -> Result<(Device, Ipv4Addr), Box<dyn Error>> {
+// fe80::/10
+pub fn is_ipv6_link_local(ip: &Ipv6Addr) -> bool {
+    (ip.segments()[0] & 0xffc0) == 0xfe80
+}
+
+// Unique local addresses, fc00::/7
+pub fn is_ipv6_unique_local(ip: &Ipv6Addr) -> bool {
+    (ip.segments()[0] & 0xfe00) == 0xfc00
+}
+
+pub fn is_ipv6_private(ip: &Ipv6Addr) -> bool {
+    is_ipv6_link_local(ip) || is_ipv6_unique_local(ip)
+}
+
+pub fn get_default_device() -> Result<(Device, LocalAddrs), Box<dyn Error>> {
     let device = Device::lookup()?.ok_or("No default device found")?;
-    let device_name = device.name.clone();
-    let local_ip = get_local_ip(&device_name).unwrap_or(Ipv4Addr::new(0, 0, 0, 0));
-    Ok((device, local_ip))
+    Ok(with_local_ips(device))
+}
+
+// Look up a capture device by its interface name (e.g. "eth0", "br0", "wg0")
+pub fn get_device(name: &str) -> Result<(Device, LocalAddrs), Box<dyn Error>> {
+    let device = Device::list()?
+        .into_iter()
+        .find(|d| d.name == name)
+        .ok_or_else(|| format!("No such interface '{}'", name))?;
+    Ok(with_local_ips(device))
+}
+
+fn with_local_ips(device: Device) -> (Device, LocalAddrs) {
+    let local = LocalAddrs::of(&device.name);
+    (device, local)
+}
+
+// Print every interface pcap can capture on, with its addresses
+pub fn print_interfaces() -> Result<(), Box<dyn Error>> {
+    let default_name = Device::lookup()?.map(|d| d.name);
+    for device in Device::list()? {
+        let marker = if Some(&device.name) == default_name.as_ref() { "*" } else { " " };
+        let addrs: Vec<String> = device.addresses.iter().map(|a| a.addr.to_string()).collect();
+        let desc = device.desc.as_deref().unwrap_or("");
+        println!("{} {:<16} {:<40} {}", marker, device.name, addrs.join(", "), desc);
+    }
+    Ok(())
+}
+
+// Default tracked ranges: RFC1918, IPv6 link-local and unique local
+const PRIVATE_NETWORKS: [&str; 5] = ["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "fe80::/10", "fc00::/7"];
+
+// Which addresses get per-host statistics
+#[derive(Clone, Default)]
+pub struct TrackFilter {
+    // Empty means the private ranges
+    pub include: Vec<IpNetwork>,
+    pub exclude: Vec<IpNetwork>,
+}
+
+impl TrackFilter {
+    pub fn tracks(&self, ip: &IpAddr) -> bool {
+        if self.exclude.iter().any(|network| network.contains(*ip)) {
+            return false;
+        }
+        if self.include.is_empty() {
+            return match ip {
+                IpAddr::V4(v4) => is_rfc1918_private(v4),
+                IpAddr::V6(v6) => is_ipv6_private(v6),
+            };
+        }
+        // A network only matches addresses of its own family (e.g. 192.168.1.0/24 or fd00::/8)
+        self.include.iter().any(|network| network.contains(*ip))
+    }
+
+    // Kernel filter passing only packets that can touch a tracked address, so the rest never reach userspace
+    pub fn bpf(&self, linktype: Linktype, local: &LocalAddrs) -> String {
+        let mut networks: Vec<String> = if self.include.is_empty() {
+            PRIVATE_NETWORKS.iter().map(|net| format!("net {}", net)).collect()
+        } else {
+            // libpcap rejects networks with host bits set, so 192.168.1.5/24 becomes 192.168.1.0/24
+            self.include.iter().map(|net| format!("net {}/{}", net.network(), net.prefix())).collect()
+        };
+        // The interface's own traffic feeds its RX/TX totals even when its address is public
+        for ip in local.ips.iter().filter(|ip| !self.tracks(ip)) {
+            let host = format!("host {}", ip);
+            if !networks.contains(&host) {
+                networks.push(host);
+            }
+        }
+        // DHCP requests come from 0.0.0.0 but still carry hostnames for tracked addresses.
+        // Tunnels are let through whole, since their outer headers say nothing about the inner hosts
+        let mut program = format!(
+            "{} or (udp port 67 and udp port 68) or ip proto 4 or ip proto 41 or ip6 proto 4 or ip6 proto 41 \
+             or ip proto gre or ip6 proto gre or udp port {} or udp port {}",
+            networks.join(" or "),
+            VXLAN_PORT,
+            GENEVE_PORT
+        );
+        // VLAN tags, MPLS and PPPoE only exist on Ethernet, and libpcap rejects "vlan" on other links.
+        // "vlan" comes last because it shifts the offsets of every test after it
+        if linktype == Linktype::ETHERNET {
+            program.push_str(" or ether proto 0x8847 or ether proto 0x8848 or ether proto 0x8864 or vlan");
+        }
+        program
+    }
+}
+
+// Kernel capture filter: the user's own expression, or one generated from the tracked networks
+pub enum CaptureFilter {
+    Custom(String),
+    Tracked,
+}
+
+impl CaptureFilter {
+    // The generated filter depends on the link type, so it is only compiled once the capture is open
+    fn apply<T: pcap::Activated + ?Sized>(&self, cap: &mut Capture<T>, linktype: Linktype, ctx: &CaptureContext, source: &str) -> Result<(), Box<dyn Error>> {
+        let program = match self {
+            CaptureFilter::Custom(program) => program.clone(),
+            CaptureFilter::Tracked => ctx.track.bpf(linktype, &ctx.local),
+        };
+        eprintln!("Capture filter on {}: {}", source, program);
+        cap.filter(&program, true).map_err(|e| format!("invalid capture filter '{}': {}", program, e))?;
+        Ok(())
+    }
+}
+
+// How fast a capture file is replayed, relative to the packet timestamps
+#[derive(Clone, Copy, Debug)]
+pub enum PlaybackSpeed {
+    Realtime,
+    Factor(f64),
+    Max,
+}
+
+impl FromStr for PlaybackSpeed {
+    type Err = String;
+
+    // Accepts "max", "realtime" or a multiplier such as "10" / "10x"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "max" => Ok(PlaybackSpeed::Max),
+            "realtime" => Ok(PlaybackSpeed::Realtime),
+            _ => {
+                let factor: f64 = s.trim_end_matches('x').parse()
+                    .map_err(|_| format!("invalid playback speed '{}'", s))?;
+                if factor <= 0.0 || !factor.is_finite() {
+                    return Err(format!("invalid playback speed '{}'", s));
+                }
+                Ok(PlaybackSpeed::Factor(factor))
+            }
+        }
+    }
+}
+
+// How often a capture thread hands its batch to the UI thread, and how many batches may be queued
+const BATCH_INTERVAL: Duration = Duration::from_millis(50);
+const BATCH_QUEUE: usize = 64;
+// How often live captures read the kernel drop counters
+const KERNEL_STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+// Channel carrying one capture thread's batches to App::on_tick
+pub fn stats_channel() -> (SyncSender<StatsBatch>, Receiver<StatsBatch>) {
+    mpsc::sync_channel(BATCH_QUEUE)
+}
+
+// Accumulates packets locally and hands them off in batches without ever waiting for the UI thread
+pub struct Batcher {
+    sender: SyncSender<StatsBatch>,
+    pending: StatsBatch,
+    last_send: Instant,
+}
+
+impl Batcher {
+    pub fn new(sender: SyncSender<StatsBatch>) -> Self {
+        Self { sender, pending: StatsBatch::default(), last_send: Instant::now() }
+    }
+
+    pub fn batch(&mut self) -> &mut StatsBatch {
+        &mut self.pending
+    }
+
+    // Send once the batch interval has passed; false once the receiving side is gone
+    pub fn maybe_send(&mut self) -> bool {
+        self.last_send.elapsed() < BATCH_INTERVAL || self.send()
+    }
+
+    pub fn send(&mut self) -> bool {
+        self.last_send = Instant::now();
+        if self.pending.is_empty() {
+            return true;
+        }
+        match self.sender.try_send(mem::take(&mut self.pending)) {
+            Ok(()) => true,
+            // The UI is behind: keep accumulating into the same batch and retry next interval
+            Err(TrySendError::Full(batch)) => {
+                self.pending = batch;
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    // Deliver the last batch, waiting for room if needed
+    pub fn finish(self) {
+        if !self.pending.is_empty() {
+            let _ = self.sender.send(self.pending);
+        }
+    }
+}
+
+// pcap's counters are 32-bit and wrap on busy links, so they are accumulated here as 64-bit totals
+struct KernelStats {
+    last: pcap::Stat,
+    totals: CaptureStats,
+    polled: Instant,
+}
+
+impl Default for KernelStats {
+    fn default() -> Self {
+        Self { last: pcap::Stat { received: 0, dropped: 0, if_dropped: 0 }, totals: CaptureStats::default(), polled: Instant::now() }
+    }
+}
+
+impl KernelStats {
+    fn update(&mut self, stat: pcap::Stat) -> CaptureStats {
+        self.totals.received += stat.received.wrapping_sub(self.last.received) as u64;
+        self.totals.dropped += stat.dropped.wrapping_sub(self.last.dropped) as u64;
+        self.totals.if_dropped += stat.if_dropped.wrapping_sub(self.last.if_dropped) as u64;
+        self.last = stat;
+        self.polled = Instant::now();
+        self.totals
+    }
+}
+
+// Addresses and filter used to classify every captured packet
+#[derive(Clone)]
+pub struct CaptureContext {
+    pub local: LocalAddrs,
+    pub track: TrackFilter,
+}
+
+// Network and transport header fields of one packet
+struct PacketInfo<'a> {
+    src: IpAddr,
+    dst: IpAddr,
+    protocol: u8,
+    src_port: u16,
+    dst_port: u16,
+    // Transport header and payload
+    transport: &'a [u8],
+}
+
+// Source/destination ports of a TCP or UDP segment, 0 for anything else
+fn transport_ports(protocol: IpNextHeaderProtocol, payload: &[u8]) -> (u16, u16) {
+    match protocol {
+        IpNextHeaderProtocols::Tcp => TcpPacket::new(payload)
+            .map_or((0, 0), |tcp| (tcp.get_source(), tcp.get_destination())),
+        IpNextHeaderProtocols::Udp => UdpPacket::new(payload)
+            .map_or((0, 0), |udp| (udp.get_source(), udp.get_destination())),
+        _ => (0, 0),
+    }
+}
+
+fn parse_ipv4(data: &[u8]) -> Option<PacketInfo<'_>> {
+    let ipv4 = Ipv4Packet::new(data)?;
+    let protocol = ipv4.get_next_level_protocol();
+    let header_len = ipv4.get_header_length() as usize * 4;
+    let total_len = (ipv4.get_total_length() as usize).min(data.len());
+    let transport = data.get(header_len..total_len).unwrap_or(&[]);
+
+    // Only the first fragment carries the transport header
+    let (src_port, dst_port, transport) = if ipv4.get_fragment_offset() == 0 {
+        let (src_port, dst_port) = transport_ports(protocol, transport);
+        (src_port, dst_port, transport)
+    } else {
+        (0, 0, &[][..])
+    };
+    Some(PacketInfo {
+        src: IpAddr::V4(ipv4.get_source()),
+        dst: IpAddr::V4(ipv4.get_destination()),
+        protocol: protocol.0,
+        src_port,
+        dst_port,
+        transport,
+    })
+}
+
+fn parse_ipv6(data: &[u8]) -> Option<PacketInfo<'_>> {
+    const HEADER_LEN: usize = 40;
+
+    let ipv6 = Ipv6Packet::new(data)?;
+    let mut protocol = ipv6.get_next_header();
+    let payload_end = (HEADER_LEN + ipv6.get_payload_length() as usize).min(data.len());
+    let mut payload = &data[HEADER_LEN..payload_end];
+
+    // Skip hop-by-hop, routing and destination options headers to reach the transport header
+    while matches!(
+        protocol,
+        IpNextHeaderProtocols::Hopopt | IpNextHeaderProtocols::Ipv6Route | IpNextHeaderProtocols::Ipv6Opts
+    ) && payload.len() >= 8 {
+        let header_len = (payload[1] as usize + 1) * 8;
+        if payload.len() < header_len {
+            break;
+        }
+        protocol = IpNextHeaderProtocol(payload[0]);
+        payload = &payload[header_len..];
+    }
+
+    let (src_port, dst_port) = transport_ports(protocol, payload);
+    Some(PacketInfo {
+        src: IpAddr::V6(ipv6.get_source()),
+        dst: IpAddr::V6(ipv6.get_destination()),
+        protocol: protocol.0,
+        src_port,
+        dst_port,
+        transport: payload,
+    })
+}
+
+// Link-layer header types the parser understands
+const LINKTYPES: [Linktype; 12] = [
+    Linktype::ETHERNET,
+    Linktype::LINUX_SLL,
+    Linktype::LINUX_SLL2,
+    Linktype::RAW,
+    Linktype::IPV4,
+    Linktype::IPV6,
+    // DLT_RAW as numbered on some BSDs and Linux before libpcap mapped it to 101
+    Linktype(12),
+    Linktype(14),
+    Linktype::NULL,
+    Linktype::LOOP,
+    Linktype::PPP,
+    Linktype::PPP_HDLC,
+];
+
+// Fail early on link types such as 802.11 radiotap instead of silently counting nothing
+fn check_linktype(linktype: Linktype, source: &str) -> Result<(), Box<dyn Error>> {
+    if LINKTYPES.contains(&linktype) {
+        return Ok(());
+    }
+    let name = linktype.get_name().unwrap_or_else(|_| linktype.0.to_string());
+    Err(format!("{}: unsupported link type {}", source, name).into())
+}
+
+// PPP protocol numbers
+const PPP_IPV4: u16 = 0x0021;
+const PPP_IPV6: u16 = 0x0057;
+// Transparent Ethernet bridging: a whole Ethernet frame follows, as in GRE, GENEVE and at the top of Ethernet captures
+const ETHERTYPE_TEB: EtherType = EtherType(0x6558);
+// ERSPAN type II, a mirrored Ethernet frame behind an 8-byte header inside GRE
+const ETHERTYPE_ERSPAN: EtherType = EtherType(0x88be);
+const VXLAN_PORT: u16 = 4789;
+const GENEVE_PORT: u16 = 6081;
+// Bound on nested tags and tunnels, so crafted packets cannot keep the parser busy
+const MAX_ENCAPSULATION: usize = 16;
+// Linux cooked capture packet types of packets addressed to and sent by this host
+const SLL_HOST: u16 = 0;
+const SLL_OUTGOING: u16 = 4;
+const ARPHRD_ETHER: u16 = 1;
+
+// Network layer payload of a frame with whatever the link layer told us about it
+#[derive(Clone)]
+struct LinkFrame<'a> {
+    ethertype: EtherType,
+    payload: &'a [u8],
+    src_mac: Option<MacAddr>,
+    // Source and destination of the outermost Ethernet header, the hop that reached the capturing interface
+    link_macs: Option<(MacAddr, MacAddr)>,
+    // Set by link layers that record the direction (Linux cooked captures, e.g. on the "any" device)
+    outgoing: Option<bool>,
+    // VLANs and overlay networks passed through on the way to the innermost IP packet
+    segments: Vec<Segment>,
+    // Index in `segments` of the VLAN tags of the current Ethernet header
+    vlan: Option<usize>,
+}
+
+impl<'a> LinkFrame<'a> {
+    fn new(ethertype: EtherType, payload: &'a [u8]) -> Self {
+        Self { ethertype, payload, src_mac: None, link_macs: None, outgoing: None, segments: Vec::new(), vlan: None }
+    }
+
+    fn set(&mut self, ethertype: EtherType, payload: &'a [u8]) {
+        self.ethertype = ethertype;
+        self.payload = payload;
+    }
+
+    fn ethernet(&mut self, data: &'a [u8]) -> Option<()> {
+        let ethernet = EthernetPacket::new(data)?;
+        self.src_mac = Some(ethernet.get_source());
+        self.link_macs.get_or_insert((ethernet.get_source(), ethernet.get_destination()));
+        self.vlan = None;
+        self.set(ethernet.get_ethertype(), &data[14..]);
+        Some(())
+    }
+
+    // The first tag of an Ethernet header is the outer one, the second the QinQ customer tag; deeper ones are ignored
+    fn push_vlan(&mut self, id: u16) {
+        match self.vlan {
+            Some(index) => {
+                if let Segment::Vlan { inner: inner @ None, .. } = &mut self.segments[index] {
+                    *inner = Some(id);
+                }
+            }
+            None => {
+                self.vlan = Some(self.segments.len());
+                self.segments.push(Segment::Vlan { outer: id, inner: None });
+            }
+        }
+    }
+}
+
+fn be16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
 }
 
-fn should_track_ip(ip: &Ipv4Addr, filter_cidr: Option<Ipv4Network>) -> bool {
-    match filter_cidr {
-        // If a CIDR is provided (e.g.,
-        Some(network) => network.contains(*ip),
-        None => is_rfc1918_private(ip),
+// IPv4 or IPv6 from the version nibble, for link types without a protocol field
+fn raw_ip(data: &[u8]) -> Option<LinkFrame<'_>> {
+    match data.first()? >> 4 {
+        4 => Some(LinkFrame::new(EtherTypes::Ipv4, data)),
+        6 => Some(LinkFrame::new(EtherTypes::Ipv6, data)),
+        _ => None,
     }
 }
 
+// BSD loopback address family: AF_INET is 2 everywhere, AF_INET6 is 10, 24, 28 or 30 depending on the OS
+fn loopback(family: u32, payload: &[u8]) -> Option<LinkFrame<'_>> {
+    match family {
+        2 => Some(LinkFrame::new(EtherTypes::Ipv4, payload)),
+        10 | 24 | 28 | 30 => Some(LinkFrame::new(EtherTypes::Ipv6, payload)),
+        _ => None,
+    }
+}
+
+// PPP frame, with or without the HDLC address/control bytes and with an optionally compressed protocol field
+fn ppp(data: &[u8]) -> Option<LinkFrame<'_>> {
+    let data = data.strip_prefix(&[0xff, 0x03]).unwrap_or(data);
+    let (protocol, payload) = if data.first()? & 1 == 1 {
+        (*data.first()? as u16, &data[1..])
+    } else {
+        (be16(data, 0)?, data.get(2..)?)
+    };
+    match protocol {
+        PPP_IPV4 => Some(LinkFrame::new(EtherTypes::Ipv4, payload)),
+        PPP_IPV6 => Some(LinkFrame::new(EtherTypes::Ipv6, payload)),
+        _ => None,
+    }
+}
+
+// Broadcast, multicast and other hosts' packets say nothing about direction
+fn sll_outgoing(packet_type: u16) -> Option<bool> {
+    match packet_type {
+        SLL_HOST => Some(false),
+        SLL_OUTGOING => Some(true),
+        _ => None,
+    }
+}
+
+// Source MAC recorded by a Linux cooked header, if the device is Ethernet-like
+fn sll_mac(hardware: u16, addr_len: usize, addr: &[u8]) -> Option<MacAddr> {
+    if hardware != ARPHRD_ETHER || addr_len != 6 {
+        return None;
+    }
+    Some(MacAddr::new(addr[0], addr[1], addr[2], addr[3], addr[4], addr[5]))
+}
+
+// Strip the link-layer header according to the capture's datalink type
+fn parse_link(linktype: Linktype, data: &[u8]) -> Option<LinkFrame<'_>> {
+    match linktype {
+        Linktype::ETHERNET => Some(LinkFrame::new(ETHERTYPE_TEB, data)),
+        // 16-byte header: packet type, ARPHRD type, address length, 8 address bytes, protocol
+        Linktype::LINUX_SLL => {
+            let header = data.get(..16)?;
+            let mut frame = LinkFrame::new(EtherType(be16(header, 14)?), &data[16..]);
+            frame.src_mac = sll_mac(be16(header, 2)?, be16(header, 4)? as usize, &header[6..14]);
+            frame.outgoing = sll_outgoing(be16(header, 0)?);
+            Some(frame)
+        }
+        // 20-byte header: protocol, reserved, interface index, ARPHRD type, packet type, address length, 8 address bytes
+        Linktype::LINUX_SLL2 => {
+            let header = data.get(..20)?;
+            let mut frame = LinkFrame::new(EtherType(be16(header, 0)?), &data[20..]);
+            frame.src_mac = sll_mac(be16(header, 8)?, header[11] as usize, &header[12..20]);
+            frame.outgoing = sll_outgoing(header[10] as u16);
+            Some(frame)
+        }
+        Linktype::RAW | Linktype::IPV4 | Linktype::IPV6 | Linktype(12) | Linktype(14) => raw_ip(data),
+        // 4-byte address family in the capturing host's byte order
+        Linktype::NULL => {
+            let family: [u8; 4] = data.get(..4)?.try_into().ok()?;
+            let family = match u32::from_le_bytes(family) {
+                le if le <= 0xffff => le,
+                _ => u32::from_be_bytes(family),
+            };
+            loopback(family, &data[4..])
+        }
+        // Same, always big-endian
+        Linktype::LOOP => loopback(u32::from_be_bytes(data.get(..4)?.try_into().ok()?), &data[4..]),
+        Linktype::PPP | Linktype::PPP_HDLC => ppp(data),
+        _ => None,
+    }
+}
+
+// GRE header: flags and version, protocol, then the optional checksum, key and sequence number fields
+fn gre(data: &[u8]) -> Option<(EtherType, &[u8])> {
+    let flags = be16(data, 0)?;
+    // Version 1 is PPTP's enhanced GRE
+    if flags & 0x7 != 0 {
+        return None;
+    }
+    let len = 4 + 4 * [0x8000, 0x2000, 0x1000].iter().filter(|&&bit| flags & bit != 0).count();
+    Some((EtherType(be16(data, 2)?), data.get(len..)?))
+}
+
+// VXLAN: flags, reserved, 24-bit VNI, reserved, then an Ethernet frame
+fn vxlan(data: &[u8]) -> Option<(EtherType, &[u8], Option<u32>)> {
+    let header = data.get(..8)?;
+    // Without the valid-VNI flag this is some other protocol on the port
+    if header[0] & 0x08 == 0 {
+        return None;
+    }
+    let vni = u32::from_be_bytes([0, header[4], header[5], header[6]]);
+    Some((ETHERTYPE_TEB, &data[8..], Some(vni)))
+}
+
+// GENEVE: version and options length, flags, protocol, 24-bit VNI, reserved, options
+fn geneve(data: &[u8]) -> Option<(EtherType, &[u8], Option<u32>)> {
+    let header = data.get(..8)?;
+    let options_len = (header[0] & 0x3f) as usize * 4;
+    let vni = u32::from_be_bytes([0, header[4], header[5], header[6]]);
+    Some((EtherType(be16(header, 2)?), data.get(8 + options_len..)?, Some(vni)))
+}
+
+// What an IP packet carries if it is a tunnel: the inner protocol, its payload and the overlay network identifier
+fn tunnel<'a>(info: &PacketInfo<'a>) -> Option<(EtherType, &'a [u8], Option<u32>)> {
+    match IpNextHeaderProtocol(info.protocol) {
+        IpNextHeaderProtocols::Ipv4 => Some((EtherTypes::Ipv4, info.transport, None)),
+        IpNextHeaderProtocols::Ipv6 => Some((EtherTypes::Ipv6, info.transport, None)),
+        IpNextHeaderProtocols::Gre => gre(info.transport).map(|(ethertype, payload)| (ethertype, payload, None)),
+        IpNextHeaderProtocols::Udp if info.dst_port == VXLAN_PORT => vxlan(info.transport.get(8..)?),
+        IpNextHeaderProtocols::Udp if info.dst_port == GENEVE_PORT => geneve(info.transport.get(8..)?),
+        _ => None,
+    }
+}
+
+// Step through one VLAN tag, MPLS label stack, Ethernet or PPPoE header
+fn next_layer(frame: &mut LinkFrame<'_>) -> Option<()> {
+    let payload = frame.payload;
+    match frame.ethertype {
+        ETHERTYPE_TEB => frame.ethernet(payload)?,
+        EtherTypes::Vlan | EtherTypes::PBridge | EtherTypes::QinQ => {
+            frame.push_vlan(be16(payload, 0)? & 0x0fff);
+            frame.set(EtherType(be16(payload, 2)?), payload.get(4..)?);
+        }
+        EtherTypes::Mpls | EtherTypes::MplsMcast => {
+            // 4-byte label stack entries down to the one with the bottom-of-stack bit
+            let mut rest = payload;
+            loop {
+                let entry = rest.get(..4)?;
+                rest = &rest[4..];
+                if entry[2] & 1 == 1 {
+                    break;
+                }
+            }
+            match rest.first()? >> 4 {
+                4 => frame.set(EtherTypes::Ipv4, rest),
+                6 => frame.set(EtherTypes::Ipv6, rest),
+                // Ethernet pseudowire behind a control word
+                0 => frame.set(ETHERTYPE_TEB, rest.get(4..)?),
+                _ => return None,
+            }
+        }
+        // 6-byte PPPoE session header, then a PPP frame
+        EtherTypes::PppoeSession => {
+            let inner = ppp(payload.get(6..)?)?;
+            frame.set(inner.ethertype, inner.payload);
+        }
+        ETHERTYPE_ERSPAN => frame.ethernet(payload.get(8..)?)?,
+        _ => return None,
+    }
+    Some(())
+}
+
+// Follow VLAN tags, MPLS labels and tunnels down to the innermost IP packet
+fn decapsulate(mut frame: LinkFrame<'_>) -> Option<(LinkFrame<'_>, PacketInfo<'_>)> {
+    // Innermost IP packet decoded so far, accounted as-is when what it carries can't be parsed
+    let mut outer = None;
+    for _ in 0..MAX_ENCAPSULATION {
+        match frame.ethertype {
+            EtherTypes::Ipv4 | EtherTypes::Ipv6 => {
+                let info = if frame.ethertype == EtherTypes::Ipv4 { parse_ipv4(frame.payload) } else { parse_ipv6(frame.payload) };
+                let Some(info) = info else {
+                    break;
+                };
+                let Some((ethertype, inner, vni)) = tunnel(&info) else {
+                    return Some((frame, info));
+                };
+                outer = Some((frame.clone(), info));
+                // The link-layer source is the tunnel endpoint, not the inner host
+                frame.src_mac = None;
+                frame.segments.extend(vni.map(Segment::Vni));
+                frame.set(ethertype, inner);
+            }
+            _ => {
+                if next_layer(&mut frame).is_none() {
+                    break;
+                }
+            }
+        }
+    }
+    outer
+}
+
+// Parse a single captured frame and account it into the shared stats
+pub fn process_packet(data: &[u8], len: u64, ts: SystemTime, linktype: Linktype, ctx: &CaptureContext, s: &mut StatsBatch) {
+    let Some((frame, info)) = parse_link(linktype, data).and_then(decapsulate) else {
+        return;
+    };
+    let (src, dst) = (info.src, info.dst);
+    s.packets += 1;
+
+    // Track total transmitted and received bytes
+    match ctx.local.direction(&src, &dst, &frame) {
+        Direction::Rx => s.rx_delta += len,
+        Direction::Tx => s.tx_delta += len,
+        Direction::Forwarded => s.fwd_delta += len,
+    }
+
+    // Track per-IP traffic for LAN IPs: the source sent the packet, the destination received it
+    if ctx.track.tracks(&src) {
+        s.traffic_delta.entry(src).or_default().tx += len;
+        if let Some(mac) = frame.src_mac {
+            s.mac_updates.insert(src, mac);
+        }
+    }
+    if ctx.track.tracks(&dst) {
+        s.traffic_delta.entry(dst).or_default().rx += len;
+    }
+
+    // Per-VLAN and per-VNI accounting, with the tracked hosts seen inside each
+    for segment in frame.segments {
+        let delta = s.segment_delta.entry(segment).or_default();
+        delta.add(len, ts);
+        delta.hosts.extend([src, dst].into_iter().filter(|ip| ctx.track.tracks(ip)));
+    }
+
+    // Track the 5-tuple flow
+    let key = FlowKey {
+        protocol: info.protocol,
+        src,
+        src_port: info.src_port,
+        dst,
+        dst_port: info.dst_port,
+    };
+    s.flow_delta.entry(key).or_insert_with(|| FlowDelta::new(ts)).add(len, ts);
+
+    // Passive hostname discovery from DHCP, DNS, mDNS, LLMNR and NetBIOS traffic
+    if info.protocol == IpNextHeaderProtocols::Udp.0 && info.transport.len() > 8 {
+        s.name_updates.extend(names::extract(src, info.src_port, info.dst_port, &info.transport[8..]));
+    }
+}
+
+// Capture timestamp of a packet as wall clock time
+fn packet_time(header: &pcap::PacketHeader) -> SystemTime {
+    UNIX_EPOCH + Duration::new(header.ts.tv_sec as u64, header.ts.tv_usec as u32 * 1000)
+}
+
 // Start a background packet capture thread
 pub fn start_capture_thread(
     device: Device, 
-    local_ip: Ipv4Addr, 
-    stats: Arc<Mutex<SharedStats>>
-    , filter_cidr: Option<Ipv4Network>
+    filter: Option<&CaptureFilter>,
+    ctx: CaptureContext,
+    sender: SyncSender<StatsBatch>,
 ) -> Result<(), Box<dyn Error>> {
+    let name = device.name.clone();
     let mut cap = Capture::from_device(device)?
         .promisc(true)
         .snaplen(65535)
         .timeout(10)
-        .open()?;
+        .open()
+        .map_err(|e| format!("cannot capture on {}: {}", name, e))?;
+    let linktype = cap.get_datalink();
+    check_linktype(linktype, &name)?;
+    if let Some(filter) = filter {
+        filter.apply(&mut cap, linktype, &ctx, &name)?;
+    }
 
+    let mut batcher = Batcher::new(sender);
+    let mut kernel_stats = KernelStats::default();
     thread::spawn(move || loop {
+        // The read timeout wakes us up regularly, so batches also go out on an idle link
         if let Ok(packet) = cap.next_packet() {
-            if let Some(ethernet) = EthernetPacket::new(packet.data) {
-                if ethernet.get_ethertype() == EtherTypes::Ipv4 {
-                    if let Some(ipv4) = Ipv4Packet::new(ethernet.payload()) {
-                        let len = packet.header.len as u64;
-                        let src = ipv4.get_source();
-                        let dst = ipv4.get_destination();
-
-                        let mut s = stats.lock().unwrap();
-
-                        // Track total transmitted and received bytes
-                        if src == local_ip {
-                            s.tx_delta += len;
-                        } else {
-                            s.rx_delta += len;
-                        }
-
-                        // Track per-IP traffic for LAN IPs
-                        if should_track_ip(&src, filter_cidr) {
-                            *s.traffic_delta.entry(src).or_insert(0) += len;
-                        }
-                        if should_track_ip(&dst, filter_cidr) {
-                            *s.traffic_delta.entry(dst).or_insert(0) += len;
-                        }
+            process_packet(packet.data, packet.header.len as u64, packet_time(packet.header), linktype, &ctx, batcher.batch());
+        }
+        if kernel_stats.polled.elapsed() >= KERNEL_STATS_INTERVAL {
+            if let Ok(stat) = cap.stats() {
+                batcher.batch().capture = Some(kernel_stats.update(stat));
+            }
+        }
+        if !batcher.maybe_send() {
+            break;
+        }
+    });
+    
+    Ok(())
+}
+
+// Replay a pcap/pcapng file through the same parser, paced by the packet timestamps
+pub fn start_file_capture_thread(
+    path: &Path,
+    speed: PlaybackSpeed,
+    filter: Option<&CaptureFilter>,
+    ctx: CaptureContext,
+    sender: SyncSender<StatsBatch>,
+) -> Result<(), Box<dyn Error>> {
+    let mut cap = Capture::from_file(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let linktype = cap.get_datalink();
+    let source = path.display().to_string();
+    check_linktype(linktype, &source)?;
+    if let Some(filter) = filter {
+        filter.apply(&mut cap, linktype, &ctx, &source)?;
+    }
+
+    let mut batcher = Batcher::new(sender);
+    thread::spawn(move || {
+        // (first packet timestamp, wall clock when it was replayed)
+        let mut origin: Option<(SystemTime, Instant)> = None;
+
+        loop {
+            let packet = match cap.next_packet() {
+                Ok(packet) => packet,
+                Err(pcap::Error::NoMorePackets) => break,
+                Err(pcap::Error::TimeoutExpired) => continue,
+                // A corrupt or truncated block fails the same way on every retry
+                Err(e) => {
+                    batcher.batch().error = Some(e.to_string());
+                    break;
+                }
+            };
+
+            let ts = packet_time(packet.header);
+            let factor = match speed {
+                PlaybackSpeed::Realtime => Some(1.0),
+                PlaybackSpeed::Factor(f) => Some(f),
+                PlaybackSpeed::Max => None,
+            };
+            if let Some(factor) = factor {
+                let (first_ts, started) = *origin.get_or_insert((ts, Instant::now()));
+                let offset = ts.duration_since(first_ts).unwrap_or_default().div_f64(factor);
+                let elapsed = started.elapsed();
+                if offset > elapsed {
+                    // Hand off what we have rather than holding it through the pause
+                    if !batcher.send() {
+                        return;
                     }
+                    thread::sleep(offset - elapsed);
                 }
             }
+
+            process_packet(packet.data, packet.header.len as u64, ts, linktype, &ctx, batcher.batch());
+            if !batcher.maybe_send() {
+                return;
+            }
         }
+        batcher.finish();
     });
-    
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const SRC_MAC: [u8; 6] = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55];
+    const DST_MAC: [u8; 6] = [0x00, 0x66, 0x77, 0x88, 0x99, 0xaa];
+
+    fn ethernet(ethertype: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = [DST_MAC, SRC_MAC].concat();
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    // 802.1Q / 802.1ad tag body: TCI, then the ethertype of what follows
+    fn tag(id: u16, ethertype: u16, payload: &[u8]) -> Vec<u8> {
+        let mut tagged = id.to_be_bytes().to_vec();
+        tagged.extend_from_slice(&ethertype.to_be_bytes());
+        tagged.extend_from_slice(payload);
+        tagged
+    }
+
+    fn ipv4(src: [u8; 4], dst: [u8; 4], protocol: u8, transport: &[u8]) -> Vec<u8> {
+        let total_len = (20 + transport.len()) as u16;
+        let mut packet = vec![0x45, 0];
+        packet.extend_from_slice(&total_len.to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0, 64, protocol, 0, 0]);
+        packet.extend_from_slice(&src);
+        packet.extend_from_slice(&dst);
+        packet.extend_from_slice(transport);
+        packet
+    }
+
+    fn ipv6(src: Ipv6Addr, dst: Ipv6Addr, next_header: u8, transport: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x60, 0, 0, 0];
+        packet.extend_from_slice(&(transport.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[next_header, 64]);
+        packet.extend_from_slice(&src.octets());
+        packet.extend_from_slice(&dst.octets());
+        packet.extend_from_slice(transport);
+        packet
+    }
+
+    fn tcp(src_port: u16, dst_port: u16) -> Vec<u8> {
+        let mut segment = [src_port.to_be_bytes(), dst_port.to_be_bytes()].concat();
+        segment.extend_from_slice(&[0; 8]); // sequence and acknowledgement numbers
+        segment.extend_from_slice(&[0x50, 0x02, 0xff, 0xff, 0, 0, 0, 0]); // offset, SYN, window, checksum, urgent
+        segment
+    }
+
+    fn udp(src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut datagram = [src_port.to_be_bytes(), dst_port.to_be_bytes(), ((8 + payload.len()) as u16).to_be_bytes(), [0, 0]].concat();
+        datagram.extend_from_slice(payload);
+        datagram
+    }
+
+    fn process(data: &[u8]) -> StatsBatch {
+        let ctx = CaptureContext { local: LocalAddrs::default(), track: TrackFilter::default() };
+        let mut batch = StatsBatch::default();
+        process_packet(data, data.len() as u64, UNIX_EPOCH, Linktype::ETHERNET, &ctx, &mut batch);
+        batch
+    }
+
+    fn decapsulate_ethernet(data: &[u8]) -> Option<(Vec<Segment>, IpAddr, IpAddr, u8)> {
+        let (frame, info) = parse_link(Linktype::ETHERNET, data).and_then(decapsulate)?;
+        Some((frame.segments, info.src, info.dst, info.protocol))
+    }
+
+    #[test]
+    fn ethernet_ipv4_tcp() {
+        let frame = ethernet(0x0800, &ipv4([192, 168, 1, 10], [192, 168, 1, 20], 6, &tcp(40000, 443)));
+        let len = frame.len() as u64;
+        let batch = process(&frame);
+
+        let (src, dst) = (IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)), IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20)));
+        assert_eq!(batch.packets, 1);
+        // No local addresses, as when reading a file, so everything counts as received
+        assert_eq!((batch.rx_delta, batch.tx_delta, batch.fwd_delta), (len, 0, 0));
+        assert_eq!(batch.traffic_delta[&src].tx, len);
+        assert_eq!(batch.traffic_delta[&dst].rx, len);
+        assert_eq!(batch.mac_updates[&src], MacAddr::new(0x00, 0x11, 0x22, 0x33, 0x44, 0x55));
+        let key = FlowKey { protocol: 6, src, src_port: 40000, dst, dst_port: 443 };
+        assert_eq!(batch.flow_delta[&key].bytes, len);
+        assert!(batch.segment_delta.is_empty());
+    }
+
+    #[test]
+    fn untracked_addresses_only_count_as_flows() {
+        let batch = process(&ethernet(0x0800, &ipv4([8, 8, 8, 8], [1, 1, 1, 1], 17, &udp(53, 53000, &[]))));
+        assert_eq!(batch.packets, 1);
+        assert!(batch.traffic_delta.is_empty());
+        assert!(batch.mac_updates.is_empty());
+        assert_eq!(batch.flow_delta.len(), 1);
+    }
+
+    #[test]
+    fn vlan_and_qinq_tags() {
+        let packet = ipv4([10, 0, 0, 1], [10, 0, 0, 2], 6, &tcp(1234, 22));
+        let (segments, src, _, _) = decapsulate_ethernet(&ethernet(0x8100, &tag(100, 0x0800, &packet))).unwrap();
+        assert_eq!(segments, [Segment::Vlan { outer: 100, inner: None }]);
+        assert_eq!(src, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+
+        // The priority bits of the TCI are not part of the VLAN ID
+        let qinq = ethernet(0x88a8, &tag(0x2000 | 200, 0x8100, &tag(10, 0x0800, &packet)));
+        let (segments, ..) = decapsulate_ethernet(&qinq).unwrap();
+        assert_eq!(segments, [Segment::Vlan { outer: 200, inner: Some(10) }]);
+
+        let batch = process(&qinq);
+        assert_eq!(batch.segment_delta[&Segment::Vlan { outer: 200, inner: Some(10) }].hosts.len(), 2);
+    }
+
+    #[test]
+    fn ethernet_ipv6_udp() {
+        let (src, dst): (Ipv6Addr, Ipv6Addr) = ("fd00::1".parse().unwrap(), "fd00::2".parse().unwrap());
+        let frame = ethernet(0x86dd, &ipv6(src, dst, 17, &udp(50000, 9999, b"payload")));
+        let batch = process(&frame);
+
+        assert_eq!(batch.packets, 1);
+        let key = FlowKey { protocol: 17, src: IpAddr::V6(src), src_port: 50000, dst: IpAddr::V6(dst), dst_port: 9999 };
+        assert_eq!(batch.flow_delta[&key].packets, 1);
+        assert_eq!(batch.traffic_delta[&IpAddr::V6(dst)].rx, frame.len() as u64);
+    }
+
+    #[test]
+    fn truncated_frames_are_ignored() {
+        let frame = ethernet(0x0800, &ipv4([192, 168, 1, 10], [192, 168, 1, 20], 6, &tcp(40000, 443)));
+        // Short Ethernet header, and an IPv4 header cut in half
+        for data in [&frame[..10], &frame[..24]] {
+            assert!(decapsulate_ethernet(data).is_none());
+            let batch = process(data);
+            assert_eq!(batch.packets, 0);
+            assert!(batch.flow_delta.is_empty());
+        }
+        // A VLAN tag without anything behind it
+        assert!(decapsulate_ethernet(&ethernet(0x8100, &[0x00])).is_none());
+    }
+
+    #[test]
+    fn non_ip_frames_are_ignored() {
+        // ARP request
+        let arp = ethernet(0x0806, &[0, 1, 8, 0, 6, 4, 0, 1]);
+        assert!(decapsulate_ethernet(&arp).is_none());
+        let batch = process(&arp);
+        assert_eq!((batch.packets, batch.rx_delta), (0, 0));
+        assert!(batch.traffic_delta.is_empty());
+    }
+
+    #[test]
+    fn counters_and_errors_only_batches_are_sent() {
+        let (sender, receiver) = stats_channel();
+        let mut batcher = Batcher::new(sender);
+        // An idle link still reports the kernel counters
+        batcher.batch().capture = Some(CaptureStats { received: 10, dropped: 2, if_dropped: 1 });
+        assert!(batcher.send());
+        let batch = receiver.try_recv().unwrap();
+        assert_eq!(batch.packets, 0);
+        assert_eq!(batch.capture.map(|c| c.lost()), Some(3));
+
+        // Nothing at all is still not worth a message
+        assert!(batcher.send());
+        assert!(receiver.try_recv().is_err());
+
+        // Nor is a read error that ends the capture
+        batcher.batch().error = Some("truncated block".to_string());
+        batcher.finish();
+        assert_eq!(receiver.try_recv().unwrap().error.as_deref(), Some("truncated block"));
+    }
+
+    #[test]
+    fn undecodable_tunnels_count_under_the_outer_header() {
+        let (src, dst) = (IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)), IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20)));
+
+        // Some other protocol on the VXLAN port: no valid-VNI flag
+        let frame = ethernet(0x0800, &ipv4([192, 168, 1, 10], [192, 168, 1, 20], 17, &udp(40000, 4789, &[0; 32])));
+        let (segments, outer_src, outer_dst, protocol) = decapsulate_ethernet(&frame).unwrap();
+        assert!(segments.is_empty());
+        assert_eq!((outer_src, outer_dst, protocol), (src, dst, 17));
+
+        // VXLAN whose inner frame is neither IP nor a known tag
+        let mut vxlan = vec![0x08, 0, 0, 0, 0, 0, 42, 0];
+        vxlan.extend(ethernet(0x0806, &[0; 28]));
+        let frame = ethernet(0x0800, &ipv4([192, 168, 1, 10], [192, 168, 1, 20], 17, &udp(40000, 4789, &vxlan)));
+        let batch = process(&frame);
+        assert_eq!(batch.packets, 1);
+        assert_eq!(batch.traffic_delta[&src].tx, frame.len() as u64);
+        let key = FlowKey { protocol: 17, src, src_port: 40000, dst, dst_port: 4789 };
+        assert_eq!(batch.flow_delta[&key].packets, 1);
+        // Still the outer packet's link-layer source
+        assert_eq!(batch.mac_updates[&src], MacAddr::new(0x00, 0x11, 0x22, 0x33, 0x44, 0x55));
+
+        // IP-in-IP with the inner header cut short
+        let frame = ethernet(0x0800, &ipv4([192, 168, 1, 10], [192, 168, 1, 20], 4, &[0x45, 0, 0]));
+        assert_eq!(decapsulate_ethernet(&frame).map(|(_, src, _, protocol)| (src, protocol)), Some((src, 4)));
+    }
+
+    #[test]
+    fn generated_filter_keeps_the_interface_addresses() {
+        let local = LocalAddrs {
+            ips: vec!["192.168.1.2".parse().unwrap(), "203.0.113.5".parse().unwrap(), "2001:db8::5".parse().unwrap()],
+            macs: vec![],
+        };
+        let program = TrackFilter::default().bpf(Linktype::ETHERNET, &local);
+        assert!(program.starts_with("net 10.0.0.0/8 or "));
+        assert!(program.contains(" or host 203.0.113.5 or host 2001:db8::5 or "));
+        // Already inside a tracked network
+        assert!(!program.contains("host 192.168.1.2"));
+        assert!(program.ends_with(" or vlan"));
+
+        let track = TrackFilter { include: vec!["192.168.1.5/24".parse().unwrap()], exclude: vec![] };
+        let program = track.bpf(Linktype::RAW, &LocalAddrs::default());
+        assert!(program.starts_with("net 192.168.1.0/24 or (udp port 67 and udp port 68)"));
+        assert!(!program.contains("host") && !program.contains("vlan"));
+    }
+}
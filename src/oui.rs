@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use pnet::util::MacAddr;
+
+// nmap-style MAC prefix list shipped with the repository
+const OUI_DATABASE: &str = include_str!("../oui_database.txt");
+
+// MAC prefix -> vendor table. Besides the usual 24-bit OUIs, the IEEE also hands out
+// MA-M (28-bit) and MA-S/OUI-36 (36-bit) blocks, which take precedence when they match.
+pub struct OuiTable {
+    // (prefix length in bits, prefix -> vendor), longest prefix first
+    prefixes: Vec<(u32, HashMap<u64, &'static str>)>,
+}
+
+impl OuiTable {
+    pub fn bundled() -> Self {
+        Self::parse(OUI_DATABASE)
+    }
+
+    // Each line is "<hex prefix> <vendor>", '#' starts a comment
+    pub fn parse(text: &'static str) -> Self {
+        let mut by_len: HashMap<u32, HashMap<u64, &'static str>> = HashMap::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((prefix, vendor)) = line.split_once(char::is_whitespace) else {
+                continue;
+            };
+            // from_str_radix would also take a leading '+', which throws the prefix length off
+            if !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+                continue;
+            }
+            let Ok(value) = u64::from_str_radix(prefix, 16) else {
+                continue;
+            };
+            let bits = prefix.len() as u32 * 4;
+            if bits > 48 {
+                continue;
+            }
+            by_len.entry(bits).or_default().insert(value, vendor.trim());
+        }
+
+        let mut prefixes: Vec<_> = by_len.into_iter().collect();
+        prefixes.sort_by_key(|(bits, _)| std::cmp::Reverse(*bits));
+        Self { prefixes }
+    }
+
+    pub fn lookup(&self, mac: MacAddr) -> Option<&'static str> {
+        let value = mac.octets().iter().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+        let found = self.prefixes.iter().find_map(|(bits, table)| table.get(&(value >> (48 - bits))).copied());
+
+        // Randomized (privacy) addresses set the locally administered bit and have no vendor
+        found.or(if mac.0 & 0x02 != 0 { Some("Locally administered") } else { None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: &str = "# comment
+        00A0C9 Intel
+        00A0C91 Intel Block
+        00A0C9123 Intel Small Block
+        0050C2 IEEE Registration Authority
+        not-hex Broken
+        +0A0C9 Signed
+        001122
+        00112233445566 Too Long
+    ";
+
+    fn mac(s: &str) -> MacAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        let table = OuiTable::parse(TABLE);
+        // 36-bit, then 28-bit, then 24-bit
+        assert_eq!(table.lookup(mac("00:a0:c9:12:34:56")), Some("Intel Small Block"));
+        assert_eq!(table.lookup(mac("00:a0:c9:1f:ff:ff")), Some("Intel Block"));
+        assert_eq!(table.lookup(mac("00:a0:c9:20:00:01")), Some("Intel"));
+        assert_eq!(table.lookup(mac("00:50:c2:00:00:01")), Some("IEEE Registration Authority"));
+        assert_eq!(table.lookup(mac("00:00:01:00:00:01")), None);
+    }
+
+    #[test]
+    fn locally_administered_fallback() {
+        let table = OuiTable::parse(TABLE);
+        assert_eq!(table.lookup(mac("02:00:00:00:00:01")), Some("Locally administered"));
+        assert_eq!(table.lookup(mac("da:a1:19:00:00:01")), Some("Locally administered"));
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let table = OuiTable::parse(TABLE);
+        assert_eq!(table.prefixes.iter().map(|(bits, t)| (*bits, t.len())).collect::<Vec<_>>(), [(36, 1), (28, 1), (24, 2)]);
+        assert_eq!(table.lookup(mac("00:11:22:33:44:55")), None);
+        assert!(!OuiTable::bundled().prefixes.is_empty());
+    }
+}
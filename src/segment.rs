@@ -0,0 +1,118 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fmt,
+    net::IpAddr,
+    time::SystemTime,
+};
+
+// VLAN or overlay network a packet was carried in
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Segment {
+    // 802.1Q tag, with the customer tag of an 802.1ad (QinQ) stack as `inner`
+    Vlan { outer: u16, inner: Option<u16> },
+    // VXLAN or GENEVE network identifier
+    Vni(u32),
+}
+
+impl Segment {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Segment::Vlan { .. } => "vlan",
+            Segment::Vni(_) => "vni",
+        }
+    }
+
+    // "10", "100.10" for QinQ, "5001"
+    pub fn id(&self) -> String {
+        match self {
+            Segment::Vlan { outer, inner: Some(inner) } => format!("{}.{}", outer, inner),
+            Segment::Vlan { outer, inner: None } => outer.to_string(),
+            Segment::Vni(vni) => vni.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Segment::Vlan { .. } => write!(f, "VLAN {}", self.id()),
+            Segment::Vni(_) => write!(f, "VNI {}", self.id()),
+        }
+    }
+}
+
+// Counters accumulated by the capture thread between two ticks
+#[derive(Default)]
+pub struct SegmentDelta {
+    pub bytes: u64,
+    pub packets: u64,
+    // Tracked addresses seen inside the segment
+    pub hosts: HashSet<IpAddr>,
+    pub last_seen: Option<SystemTime>,
+}
+
+impl SegmentDelta {
+    pub fn add(&mut self, len: u64, ts: SystemTime) {
+        self.bytes += len;
+        self.packets += 1;
+        self.last_seen = self.last_seen.max(Some(ts));
+    }
+
+    pub fn merge(&mut self, other: SegmentDelta) {
+        self.bytes += other.bytes;
+        self.packets += other.packets;
+        self.hosts.extend(other.hosts);
+        self.last_seen = self.last_seen.max(other.last_seen);
+    }
+}
+
+// Lifetime counters of one segment
+pub struct SegmentStats {
+    pub bytes: u64,
+    pub packets: u64,
+    pub hosts: HashSet<IpAddr>,
+    pub last_seen: Option<SystemTime>,
+    // Bytes/s during the last tick
+    pub rate: f64,
+}
+
+#[derive(Default)]
+pub struct SegmentTable {
+    segments: HashMap<Segment, SegmentStats>,
+}
+
+impl SegmentTable {
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    // Fold one tick worth of deltas into the table
+    pub fn merge(&mut self, deltas: HashMap<Segment, SegmentDelta>, tick_secs: f64) {
+        for stats in self.segments.values_mut() {
+            stats.rate = 0.0;
+        }
+        for (segment, delta) in deltas {
+            let stats = self.segments.entry(segment).or_insert_with(|| SegmentStats {
+                bytes: 0,
+                packets: 0,
+                hosts: HashSet::new(),
+                last_seen: None,
+                rate: 0.0,
+            });
+            stats.bytes += delta.bytes;
+            stats.packets += delta.packets;
+            stats.hosts.extend(delta.hosts);
+            stats.last_seen = stats.last_seen.max(delta.last_seen);
+            stats.rate = delta.bytes as f64 / tick_secs;
+        }
+    }
+
+    // Heaviest segments first
+    pub fn top(&self, n: usize) -> Vec<(&Segment, &SegmentStats)> {
+        let mut segments: Vec<_> = self.segments.iter().collect();
+        segments.sort_by_key(|(_, stats)| Reverse(stats.bytes));
+        segments.truncate(n);
+        segments
+    }
+}
@@ -0,0 +1,160 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use crate::flow::{protocol_name, FlowKey};
+
+const ICMP: u8 = 1;
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+const ICMPV6: u8 = 58;
+
+// Name of a well-known service port
+fn service_name(protocol: u8, port: u16) -> Option<&'static str> {
+    let name = match (protocol, port) {
+        (TCP, 20 | 21) => "FTP",
+        (TCP, 22) => "SSH",
+        (TCP, 23) => "Telnet",
+        (TCP, 25 | 465 | 587) => "SMTP",
+        (_, 53) => "DNS",
+        (UDP, 67 | 68) => "DHCP",
+        (UDP, 69) => "TFTP",
+        (TCP, 80 | 8080) => "HTTP",
+        (_, 88) => "Kerberos",
+        (TCP, 110 | 995) => "POP3",
+        (UDP, 123) => "NTP",
+        (_, 137..=139) => "NetBIOS",
+        (TCP, 143 | 993) => "IMAP",
+        (UDP, 161 | 162) => "SNMP",
+        (_, 389 | 636) => "LDAP",
+        (TCP, 443 | 8443) => "HTTPS",
+        (UDP, 443) => "QUIC",
+        (TCP, 445) => "SMB",
+        (UDP, 500 | 4500) => "IPsec",
+        (UDP, 514) => "Syslog",
+        (TCP, 548) => "AFP",
+        (TCP, 631) => "IPP",
+        (TCP, 853) => "DNS over TLS",
+        (_, 1194) => "OpenVPN",
+        (TCP, 1883 | 8883) => "MQTT",
+        (UDP, 1900) => "SSDP",
+        (_, 2049) => "NFS",
+        (TCP, 3306) => "MySQL",
+        (_, 3389) => "RDP",
+        (UDP, 3478) => "STUN",
+        (TCP, 5222) => "XMPP",
+        (_, 5060 | 5061) => "SIP",
+        (UDP, 5353) => "mDNS",
+        (UDP, 5355) => "LLMNR",
+        (TCP, 5432) => "PostgreSQL",
+        (TCP, 5900) => "VNC",
+        (TCP, 6379) => "Redis",
+        (_, 6881..=6889) => "BitTorrent",
+        (TCP, 7000) => "AirPlay",
+        (TCP, 9100) => "Printer",
+        (TCP, 32400) => "Plex",
+        (UDP, 51820) => "WireGuard",
+        _ => return None,
+    };
+    Some(name)
+}
+
+// Application service of a flow: protocol plus the well-known port, 0 when neither port is one
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Service {
+    pub protocol: u8,
+    pub port: u16,
+}
+
+impl Service {
+    pub fn of(key: &FlowKey) -> Self {
+        // The server side is normally the destination, but replies come from it
+        let port = [key.dst_port, key.src_port]
+            .into_iter()
+            .find(|&port| key.has_ports() && service_name(key.protocol, port).is_some())
+            .unwrap_or(0);
+        Self { protocol: key.protocol, port }
+    }
+
+    // "HTTPS", "TCP other", "ICMP"
+    pub fn name(&self) -> String {
+        match service_name(self.protocol, self.port) {
+            Some(name) => name.to_string(),
+            None if self.protocol == TCP || self.protocol == UDP => format!("{} other", self.class()),
+            None => protocol_name(self.protocol).to_string(),
+        }
+    }
+
+    // Coarse protocol group shown in the protocol split
+    pub fn class(&self) -> &'static str {
+        match self.protocol {
+            TCP => "TCP",
+            UDP => "UDP",
+            ICMP | ICMPV6 => "ICMP",
+            _ => "Other",
+        }
+    }
+}
+
+// Bytes and packets of one service during a tick
+pub type ServiceDeltas = HashMap<Service, (u64, u64)>;
+
+// Lifetime counters of one service
+#[derive(Default)]
+pub struct ServiceStats {
+    pub bytes: u64,
+    pub packets: u64,
+    // Bytes/s during the last tick
+    pub rate: f64,
+}
+
+// Traffic per service, for the whole network or for one host
+#[derive(Default)]
+pub struct ServiceTable {
+    services: HashMap<Service, ServiceStats>,
+}
+
+impl ServiceTable {
+    // Fold one tick worth of deltas into the table
+    pub fn merge(&mut self, deltas: &ServiceDeltas, tick_secs: f64) {
+        for stats in self.services.values_mut() {
+            stats.rate = 0.0;
+        }
+        for (service, &(bytes, packets)) in deltas {
+            let stats = self.services.entry(*service).or_default();
+            stats.bytes += bytes;
+            stats.packets += packets;
+            stats.rate = bytes as f64 / tick_secs;
+        }
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.services.values().map(|stats| stats.bytes).sum()
+    }
+
+    // Heaviest services first
+    pub fn top(&self, n: usize) -> Vec<(&Service, &ServiceStats)> {
+        let mut services: Vec<_> = self.services.iter().collect();
+        services.sort_by_key(|(_, stats)| Reverse(stats.bytes));
+        services.truncate(n);
+        services
+    }
+
+    // TCP / UDP / ICMP / Other totals, heaviest first
+    pub fn classes(&self) -> Vec<(&'static str, ServiceStats)> {
+        let mut classes: Vec<(&'static str, ServiceStats)> = Vec::new();
+        for (service, stats) in &self.services {
+            let index = match classes.iter().position(|(class, _)| *class == service.class()) {
+                Some(index) => index,
+                None => {
+                    classes.push((service.class(), ServiceStats::default()));
+                    classes.len() - 1
+                }
+            };
+            let class = &mut classes[index].1;
+            class.bytes += stats.bytes;
+            class.packets += stats.packets;
+            class.rate += stats.rate;
+        }
+        classes.sort_by_key(|(_, stats)| Reverse(stats.bytes));
+        classes
+    }
+}
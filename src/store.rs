@@ -0,0 +1,298 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use rusqlite::{params, Connection};
+
+// Round-robin resolutions: (bucket size, how long buckets of that size are kept)
+const RESOLUTIONS: [(u64, u64); 3] = [
+    (60, 24 * 3600),              // 1-minute buckets for a day
+    (3600, 30 * 24 * 3600),       // hourly buckets for a month
+    (24 * 3600, 365 * 24 * 3600), // daily buckets for a year
+];
+
+// Counters are written in batches rather than every tick
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Host,
+    Interface,
+}
+
+impl Kind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Host => "host",
+            Kind::Interface => "interface",
+        }
+    }
+}
+
+// One row of a usage report
+pub struct HostUsage {
+    pub ip: String,
+    pub name: Option<String>,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+
+// Per-host and per-interface byte counters persisted to SQLite
+pub struct HistoryStore {
+    conn: Connection,
+    pending: HashMap<(Kind, String), (u64, u64)>,
+    names: HashMap<String, String>,
+    last_flush: Option<SystemTime>,
+}
+
+impl HistoryStore {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, Box<dyn Error>> {
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS traffic (
+                 resolution INTEGER NOT NULL,
+                 bucket     INTEGER NOT NULL,
+                 kind       TEXT    NOT NULL,
+                 key        TEXT    NOT NULL,
+                 rx_bytes   INTEGER NOT NULL,
+                 tx_bytes   INTEGER NOT NULL,
+                 PRIMARY KEY (resolution, bucket, kind, key)
+             );
+             CREATE TABLE IF NOT EXISTS totals (
+                 kind     TEXT    NOT NULL,
+                 key      TEXT    NOT NULL,
+                 rx_bytes INTEGER NOT NULL,
+                 tx_bytes INTEGER NOT NULL,
+                 PRIMARY KEY (kind, key)
+             );
+             CREATE TABLE IF NOT EXISTS host_names (
+                 ip   TEXT PRIMARY KEY,
+                 name TEXT NOT NULL
+             );",
+        )?;
+        Ok(Self {
+            conn,
+            pending: HashMap::new(),
+            names: HashMap::new(),
+            last_flush: None,
+        })
+    }
+
+    // Lifetime (rx, tx) totals of every key of a kind, used to restore counters after a restart
+    pub fn load_totals(&self, kind: Kind) -> Result<HashMap<String, (u64, u64)>, Box<dyn Error>> {
+        let mut stmt = self.conn.prepare("SELECT key, rx_bytes, tx_bytes FROM totals WHERE kind = ?1")?;
+        let rows = stmt.query_map(params![kind.as_str()], |row| {
+            Ok((row.get::<_, String>(0)?, (row.get::<_, i64>(1)? as u64, row.get::<_, i64>(2)? as u64)))
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    pub fn record(&mut self, kind: Kind, key: &str, rx: u64, tx: u64) {
+        if rx == 0 && tx == 0 {
+            return;
+        }
+        let entry = self.pending.entry((kind, key.to_string())).or_insert((0, 0));
+        entry.0 += rx;
+        entry.1 += tx;
+    }
+
+    pub fn record_name(&mut self, ip: &str, name: &str) {
+        self.names.insert(ip.to_string(), name.to_string());
+    }
+
+    // Write pending counters once the flush interval has passed, true if they were written
+    pub fn maybe_flush(&mut self, now: SystemTime) -> Result<bool, Box<dyn Error>> {
+        match self.last_flush {
+            Some(last) if now.duration_since(last).unwrap_or_default() < FLUSH_INTERVAL => Ok(false),
+            _ => self.flush(now).map(|()| true),
+        }
+    }
+
+    // Add pending counters to the bucket containing `now` at every resolution and drop expired buckets.
+    // Pending counters are only cleared once the transaction commits, so a failed write is retried next time
+    pub fn flush(&mut self, now: SystemTime) -> Result<(), Box<dyn Error>> {
+        self.last_flush = Some(now);
+        let now_secs = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+        let tx = self.conn.transaction()?;
+        {
+            let mut upsert_bucket = tx.prepare_cached(
+                "INSERT INTO traffic (resolution, bucket, kind, key, rx_bytes, tx_bytes)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT (resolution, bucket, kind, key)
+                 DO UPDATE SET rx_bytes = rx_bytes + excluded.rx_bytes, tx_bytes = tx_bytes + excluded.tx_bytes",
+            )?;
+            let mut upsert_total = tx.prepare_cached(
+                "INSERT INTO totals (kind, key, rx_bytes, tx_bytes) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (kind, key)
+                 DO UPDATE SET rx_bytes = rx_bytes + excluded.rx_bytes, tx_bytes = tx_bytes + excluded.tx_bytes",
+            )?;
+            for ((kind, key), &(rx, tx_bytes)) in &self.pending {
+                for (resolution, _) in RESOLUTIONS {
+                    let bucket = now_secs - now_secs % resolution;
+                    upsert_bucket.execute(params![resolution as i64, bucket as i64, kind.as_str(), key, rx as i64, tx_bytes as i64])?;
+                }
+                upsert_total.execute(params![kind.as_str(), key, rx as i64, tx_bytes as i64])?;
+            }
+
+            let mut upsert_name = tx.prepare_cached(
+                "INSERT INTO host_names (ip, name) VALUES (?1, ?2) ON CONFLICT (ip) DO UPDATE SET name = excluded.name",
+            )?;
+            for (ip, name) in &self.names {
+                upsert_name.execute(params![ip, name])?;
+            }
+
+            for (resolution, retention) in RESOLUTIONS {
+                tx.execute(
+                    "DELETE FROM traffic WHERE resolution = ?1 AND bucket < ?2",
+                    params![resolution as i64, now_secs.saturating_sub(retention) as i64],
+                )?;
+            }
+        }
+        tx.commit()?;
+        self.pending.clear();
+        self.names.clear();
+        Ok(())
+    }
+
+    // Top hosts by total bytes over the last `period`, from the finest resolution that still covers it
+    pub fn top_hosts(&self, period: Duration, limit: usize) -> Result<Vec<HostUsage>, Box<dyn Error>> {
+        let period_secs = period.as_secs();
+        let (resolution, _) = RESOLUTIONS
+            .iter()
+            .find(|(_, retention)| period_secs <= *retention)
+            .unwrap_or(&RESOLUTIONS[RESOLUTIONS.len() - 1]);
+        let now_secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let since = now_secs.saturating_sub(period_secs);
+
+        let mut stmt = self.conn.prepare(
+            "SELECT t.key, n.name, SUM(t.rx_bytes), SUM(t.tx_bytes)
+             FROM traffic t LEFT JOIN host_names n ON n.ip = t.key
+             WHERE t.resolution = ?1 AND t.kind = 'host' AND t.bucket >= ?2 - (?2 % ?1)
+             GROUP BY t.key
+             ORDER BY SUM(t.rx_bytes) + SUM(t.tx_bytes) DESC
+             LIMIT ?3",
+        )?;
+        let rows = stmt.query_map(params![*resolution as i64, since as i64, limit as i64], |row| {
+            Ok(HostUsage {
+                ip: row.get(0)?,
+                name: row.get(1)?,
+                rx_bytes: row.get::<_, i64>(2)? as u64,
+                tx_bytes: row.get::<_, i64>(3)? as u64,
+            })
+        })?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Midnight UTC, so that minute, hour and day buckets all start here
+    const DAY_START: u64 = 20_000 * 24 * 3600;
+
+    fn store() -> HistoryStore {
+        HistoryStore::with_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    // (bucket, rx, tx) rows of one key at one resolution
+    fn buckets(store: &HistoryStore, resolution: u64, key: &str) -> Vec<(u64, u64, u64)> {
+        let mut stmt = store
+            .conn
+            .prepare("SELECT bucket, rx_bytes, tx_bytes FROM traffic WHERE resolution = ?1 AND key = ?2 ORDER BY bucket")
+            .unwrap();
+        let rows = stmt
+            .query_map(params![resolution as i64, key], |row| {
+                Ok((row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)? as u64, row.get::<_, i64>(2)? as u64))
+            })
+            .unwrap();
+        rows.collect::<Result<_, _>>().unwrap()
+    }
+
+    #[test]
+    fn flushes_roll_up_into_every_resolution() {
+        let mut store = store();
+        store.record(Kind::Host, "10.0.0.1", 100, 10);
+        store.flush(at(DAY_START + 5)).unwrap();
+        store.record(Kind::Host, "10.0.0.1", 50, 5);
+        store.flush(at(DAY_START + 30)).unwrap();
+        store.record(Kind::Host, "10.0.0.1", 1, 1);
+        store.flush(at(DAY_START + 90)).unwrap();
+
+        let minute = buckets(&store, 60, "10.0.0.1");
+        assert_eq!(minute, [(DAY_START, 150, 15), (DAY_START + 60, 1, 1)]);
+        assert_eq!(buckets(&store, 3600, "10.0.0.1"), [(DAY_START, 151, 16)]);
+        assert_eq!(buckets(&store, 24 * 3600, "10.0.0.1"), [(DAY_START, 151, 16)]);
+        assert_eq!(store.load_totals(Kind::Host).unwrap()["10.0.0.1"], (151, 16));
+        assert!(store.load_totals(Kind::Interface).unwrap().is_empty());
+    }
+
+    #[test]
+    fn expired_buckets_are_pruned_per_resolution() {
+        let mut store = store();
+        store.record(Kind::Interface, "eth0", 100, 100);
+        store.flush(at(DAY_START)).unwrap();
+        // Two days later the minute buckets are gone but the hourly and daily ones remain
+        store.record(Kind::Interface, "eth0", 1, 1);
+        store.flush(at(DAY_START + 2 * 24 * 3600)).unwrap();
+
+        assert_eq!(buckets(&store, 60, "eth0").len(), 1);
+        assert_eq!(buckets(&store, 3600, "eth0").len(), 2);
+        assert_eq!(buckets(&store, 24 * 3600, "eth0").len(), 2);
+        // Lifetime totals are never pruned
+        assert_eq!(store.load_totals(Kind::Interface).unwrap()["eth0"], (101, 101));
+    }
+
+    #[test]
+    fn top_hosts_reads_the_finest_resolution_covering_the_period() {
+        let mut store = store();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        // Different figures per resolution show which one a report read
+        for (resolution, host, bytes) in [(60, "10.0.0.1", 1), (3600, "10.0.0.2", 2), (24 * 3600, "10.0.0.3", 3)] {
+            store
+                .conn
+                .execute(
+                    "INSERT INTO traffic VALUES (?1, ?2, 'host', ?3, ?4, 0)",
+                    params![resolution, (now - now % resolution) as i64, host, bytes],
+                )
+                .unwrap();
+        }
+        store.record_name("10.0.0.2", "nas");
+        store.flush(at(now)).unwrap();
+
+        let top = |period: u64| store.top_hosts(Duration::from_secs(period), 10).unwrap();
+        assert_eq!(top(3600).iter().map(|h| h.ip.as_str()).collect::<Vec<_>>(), ["10.0.0.1"]);
+        let week = top(7 * 24 * 3600);
+        assert_eq!((week[0].ip.as_str(), week[0].name.as_deref(), week[0].rx_bytes), ("10.0.0.2", Some("nas"), 2));
+        assert_eq!(top(90 * 24 * 3600)[0].ip, "10.0.0.3");
+        // Longer than every retention falls back to the daily buckets
+        assert_eq!(top(2 * 365 * 24 * 3600)[0].ip, "10.0.0.3");
+    }
+
+    #[test]
+    fn failed_flushes_keep_the_pending_counters() {
+        let mut store = store();
+        store.conn.execute_batch("DROP TABLE host_names").unwrap();
+        store.record(Kind::Host, "10.0.0.1", 100, 10);
+        store.record_name("10.0.0.1", "laptop");
+        assert!(store.flush(at(DAY_START)).is_err());
+        assert!(store.load_totals(Kind::Host).unwrap().is_empty());
+
+        store.conn.execute_batch("CREATE TABLE host_names (ip TEXT PRIMARY KEY, name TEXT NOT NULL)").unwrap();
+        store.flush(at(DAY_START + 10)).unwrap();
+        assert_eq!(store.load_totals(Kind::Host).unwrap()["10.0.0.1"], (100, 10));
+        assert_eq!(buckets(&store, 60, "10.0.0.1"), [(DAY_START, 100, 10)]);
+        assert!(store.pending.is_empty() && store.names.is_empty());
+    }
+}
@@ -1,4 +1,5 @@
-use std::{io, sync::{Arc, Mutex}, time::{Duration, Instant}};
+use std::{io, net::IpAddr, sync::mpsc::Receiver, time::{Duration, SystemTime}};
+use chrono::{DateTime, Local};
 use crossterm::{
     event::{self, Event, KeyCode},
     execute,
@@ -6,22 +7,23 @@ use crossterm::{
 };
 use ratatui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     symbols::Marker,
     text::{Line, Span},
     widgets::{
         canvas::{Canvas, Line as CanvasLine},
-        Block, Borders, Cell, Paragraph, Row, Table,
+        Block, Borders, Cell, Paragraph, Row, Table, TableState,
     },
-    Terminal,
+    Frame, Terminal,
 };
 
-use crate::app::{App, SharedStats};
-use crate::constants::TICK_RATE_MS;
+use crate::app::{App, InterfaceStats, StatsBatch, View};
+use crate::flow::protocol_name;
+use crate::services::ServiceTable;
 use crate::util::{format_bps, format_bytes_total};
 
-pub fn run(stats: Arc<Mutex<SharedStats>>, device_name: &str) -> io::Result<()> {
+pub fn run(mut app: App, stats: Vec<Receiver<StatsBatch>>) -> io::Result<()> {
     // Initialize terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -29,8 +31,8 @@ pub fn run(stats: Arc<Mutex<SharedStats>>, device_name: &str) -> io::Result<()>
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let app = App::new();
-    let res = run_app_loop(&mut terminal, app, stats, device_name);
+    let res = run_app_loop(&mut terminal, &mut app, stats);
+    app.shutdown();
 
     // Cleanup
     disable_raw_mode()?;
@@ -40,188 +42,791 @@ pub fn run(stats: Arc<Mutex<SharedStats>>, device_name: &str) -> io::Result<()>
     if let Err(err) = res {
         println!("Error: {:?}", err)
     }
+    if let Some(err) = &app.history_error {
+        eprintln!("Warning: the last traffic history write failed: {}", err);
+    }
     Ok(())
 }
 
 fn run_app_loop<B: ratatui::backend::Backend>(
     terminal: &mut Terminal<B>,
-    mut app: App,
-    stats: Arc<Mutex<SharedStats>>,
-    device_name: &str,
+    app: &mut App,
+    stats: Vec<Receiver<StatsBatch>>,
 ) -> io::Result<()> {
-    let tick_rate = Duration::from_millis(TICK_RATE_MS);
-
+    // Scroll position of the talkers table; the selection itself lives in App
+    let mut talker_table = TableState::default();
+    // Rows the middle table had room for in the last frame, how far PageUp/PageDown move
+    let mut page = 1;
     loop {
         terminal.draw(|f| {
+            // ============= Host detail pane ============
+            if let Some(ip) = app.detail_host {
+                let chunks = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Min(10), Constraint::Length(1)].as_ref())
+                    .split(f.size());
+                draw_host_detail(f, chunks[0], app, ip);
+                let help = Paragraph::new(" 'Enter'/'Esc' back to the host list | Press 'q' to quit")
+                    .style(Style::default().bg(Color::Rgb(20, 20, 20)));
+                f.render_widget(help, chunks[1]);
+                return;
+            }
+
             // ============= whole screen layout ============
+            // The per-interface breakdown only shows up when capturing on several interfaces
+            let breakdown_height = if app.interfaces.len() > 1 { app.interfaces.len() as u16 + 3 } else { 0 };
             let main_chunks = Layout::default()
                 .direction(Direction::Vertical)
                 .margin(0)
                 .constraints([
                     Constraint::Length(16), // Upside Net Box
+                    Constraint::Length(breakdown_height), // Interface Breakdown
                     Constraint::Min(10),    // Middle Table
                     Constraint::Length(1),  // Bottom Status Bar
                 ].as_ref())
                 .split(f.size());
 
             // ============= Top Net Monitor Box ============
-            let net_block = Block::default()
-                .borders(Borders::ALL)
-                .title(format!(" Net Monitor [{}] ", device_name))
-                .border_type(ratatui::widgets::BorderType::Rounded)
-                .border_style(Style::default().fg(Color::Cyan));
-            f.render_widget(net_block.clone(), main_chunks[0]);
-
-            let inner_area = net_block.inner(main_chunks[0]);
-            let graph_chunks = Layout::default()
-                .direction(Direction::Horizontal)
-                .constraints([Constraint::Percentage(75), Constraint::Percentage(25)].as_ref())
-                .split(inner_area);
-
-            // ======== Left Graphs (Download/Upload) ========
-            let chart_chunks = Layout::default()
-                .direction(Direction::Vertical)
-                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
-                .split(graph_chunks[0]);
-
-            let max_rx = app.rx_history.iter().cloned().fold(100.0, f64::max);
-            let max_tx = app.tx_history.iter().cloned().fold(100.0, f64::max);
-            let x_limit = app.rx_history.len() as f64;
-
-            // Download Canvas
-            let download_canvas = Canvas::default()
-                .block(Block::default().title(" Download ").title_style(Style::default().fg(Color::Red)))
-                .marker(Marker::Braille)
-                .x_bounds([0.0, x_limit])
-                .y_bounds([0.0, max_rx])
-                .paint(|ctx| {
-                    for (i, &val) in app.rx_history.iter().enumerate() {
-                        ctx.draw(&CanvasLine {
-                            x1: i as f64,
-                            y1: 0.0,
-                            x2: i as f64,
-                            y2: val,
-                            color: Color::Red,
-                        });
-                    }
-                });
-            f.render_widget(download_canvas, chart_chunks[0]);
-
-            // Upload Canvas
-            let upload_canvas = Canvas::default()
-                .block(Block::default().title(" Upload ").title_style(Style::default().fg(Color::Blue)))
-                .marker(Marker::Braille)
-                .x_bounds([0.0, x_limit])
-                .y_bounds([0.0, max_tx])
-                .paint(|ctx| {
-                    for (i, &val) in app.tx_history.iter().enumerate() {
-                        ctx.draw(&CanvasLine {
-                            x1: i as f64,
-                            y1: 0.0,
-                            x2: i as f64,
-                            y2: val,
-                            color: Color::Blue,
-                        });
-                    }
-                });
-            f.render_widget(upload_canvas, chart_chunks[1]);
+            let title = match app.selected_interface {
+                Some(_) => format!(" Net Monitor [{}] ", app.displayed_interface().name),
+                None => format!(" Net Monitor [{}] ", app.interfaces.iter().map(|i| i.name.as_str()).collect::<Vec<_>>().join(", ")),
+            };
+            draw_traffic_box(f, main_chunks[0], &title, app.displayed_interface());
 
-            // textual stats on the right
-            let text_chunks = Layout::default()
-                .direction(Direction::Vertical)
-                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
-                .split(graph_chunks[1]);
-
-            let current_rx_bps = (*app.rx_history.last().unwrap_or(&0.0)) * (1000.0 / TICK_RATE_MS as f64);
-            let current_tx_bps = (*app.tx_history.last().unwrap_or(&0.0)) * (1000.0 / TICK_RATE_MS as f64);
-            
-            let peak_rx_bps = app.peak_rx_record.0;
-            let peak_tx_bps = app.peak_tx_record.0;
-
-            let rx_text = vec![
-                Line::from(vec![Span::raw("▼ "), Span::styled(format_bps(current_rx_bps), Style::default().fg(Color::White).add_modifier(Modifier::BOLD))]),
-                Line::from(vec![Span::styled("  Peak: ", Style::default().fg(Color::DarkGray)), Span::raw(format_bps(peak_rx_bps))]),
-                Line::from(vec![Span::styled("  Tot:  ", Style::default().fg(Color::DarkGray)), Span::raw(format_bytes_total(app.total_rx_bytes))]),
-            ];
-            f.render_widget(Paragraph::new(rx_text).block(Block::default().style(Style::default().fg(Color::Red))), text_chunks[0]);
+            // ============= Interface Breakdown ============
+            if breakdown_height > 0 {
+                draw_interface_breakdown(f, main_chunks[1], app);
+            }
 
-            let tx_text = vec![
-                Line::from(vec![Span::raw("▲ "), Span::styled(format_bps(current_tx_bps), Style::default().fg(Color::White).add_modifier(Modifier::BOLD))]),
-                Line::from(vec![Span::styled("  Peak: ", Style::default().fg(Color::DarkGray)), Span::raw(format_bps(peak_tx_bps))]),
-                Line::from(vec![Span::styled("  Tot:  ", Style::default().fg(Color::DarkGray)), Span::raw(format_bytes_total(app.total_tx_bytes))]),
-            ];
-            f.render_widget(Paragraph::new(tx_text).block(Block::default().style(Style::default().fg(Color::Blue))), text_chunks[1]);
-
-            // ============= Middle Top Talkers Table ============
-            let header_cells = ["IP Address", "Avg Bandwidth", "Peak Rate", "Peak Time", "Status"]
-                .iter()
-                .map(|h| Cell::from(*h).style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)));
-            let header = Row::new(header_cells)
-                .style(Style::default().bg(Color::Rgb(40, 40, 40)))
-                .height(1)
-                .bottom_margin(0);
-
-            let rows = app.top_talkers.iter().take(25).map(|(ip, avg_bps, peak_bps, peak_time)| {
-                let avg_color = if *avg_bps > 1_000_000.0 { Color::Red } else if *avg_bps > 100_000.0 { Color::LightYellow } else { Color::Green };
-                let peak_color = if *peak_bps > 1_000_000.0 { Color::Magenta } else { Color::Cyan };
-
-                Row::new(vec![
-                    Cell::from(ip.to_string()),
-                    Cell::from(format_bps(*avg_bps)).style(Style::default().fg(avg_color)),
-                    Cell::from(format_bps(*peak_bps)).style(Style::default().fg(peak_color)),
-                    Cell::from(peak_time.format("%H:%M:%S").to_string()).style(Style::default().fg(Color::DarkGray)),
-                    Cell::from("Active"),
-                ]).height(1)
-            });
-
-            let table = Table::new(
-                rows,
-                [
-                    Constraint::Percentage(20),
-                    Constraint::Percentage(20),
-                    Constraint::Percentage(20),
-                    Constraint::Percentage(20),
-                    Constraint::Percentage(20),
-                ]
-            )
-            .header(header)
-            .block(Block::default().title(" Local Network Traffic ").borders(Borders::ALL).border_type(ratatui::widgets::BorderType::Rounded));
-            f.render_widget(table, main_chunks[1]);
+            // ============= Middle Table ============
+            page = main_chunks[2].height.saturating_sub(3).max(1) as isize;
+            match app.view {
+                View::Talkers => draw_talkers(f, main_chunks[2], app, &mut talker_table),
+                View::Flows => draw_flows(f, main_chunks[2], app),
+                View::Groups => draw_groups(f, main_chunks[2], app),
+                View::Segments => draw_segments(f, main_chunks[2], app),
+                View::Services => draw_services(f, main_chunks[2], app),
+                View::Conversations => draw_conversations(f, main_chunks[2], app),
+                View::Destinations => draw_destinations(f, main_chunks[2], app),
+            }
 
             // ============ Bottom Status Bar ============
-            let global_rx_time = app.peak_rx_record.1.format("%H:%M:%S").to_string();
-            let global_tx_time = app.peak_tx_record.1.format("%H:%M:%S").to_string();
+            let global = &app.aggregate;
+            let global_rx_time = global.peak_rx_record.1.format("%H:%M:%S").to_string();
+            let global_tx_time = global.peak_tx_record.1.format("%H:%M:%S").to_string();
 
-            let status_content = Line::from(vec![
+            let mut spans = vec![
                 Span::styled(" GLOBAL RECORDS ", Style::default().bg(Color::White).fg(Color::Black).add_modifier(Modifier::BOLD)),
                 Span::raw(" | "),
                 Span::styled("MAX RX: ", Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)),
-                Span::raw(format!("{} ", format_bps(app.peak_rx_record.0))),
+                Span::raw(format!("{} ", format_bps(global.peak_rx_record.0))),
                 Span::styled(format!("(@{})", global_rx_time), Style::default().fg(Color::DarkGray)),
                 Span::raw(" | "),
                 Span::styled("MAX TX: ", Style::default().fg(Color::Blue).add_modifier(Modifier::BOLD)),
-                Span::raw(format!("{} ", format_bps(app.peak_tx_record.0))),
+                Span::raw(format!("{} ", format_bps(global.peak_tx_record.0))),
                 Span::styled(format!("(@{})", global_tx_time), Style::default().fg(Color::DarkGray)),
-                Span::raw(" | Press 'q' to quit"),
-            ]);
+            ];
+
+            // Kernel counters tell whether the figures above are complete
+            if let Some(capture) = global.capture {
+                let (label, style) = if global.drops_rising() {
+                    (" DROPPING ", Style::default().fg(Color::White).bg(Color::Red).add_modifier(Modifier::BOLD))
+                } else {
+                    ("", Style::default().fg(Color::DarkGray))
+                };
+                spans.push(Span::raw(" | "));
+                spans.push(Span::styled(
+                    format!(
+                        "{}rcvd {} drop {} ifdrop {} ({:.2}%)",
+                        label, capture.received, capture.dropped, capture.if_dropped, capture.loss_ratio() * 100.0
+                    ),
+                    style,
+                ));
+            }
+
+            // Like kernel drops, flows evicted from a full table mean the flow figures are incomplete
+            if app.flows.evicted() > 0 {
+                let (label, style) = if app.flows_evicting() {
+                    (" FLOW TABLE FULL ", Style::default().fg(Color::White).bg(Color::Red).add_modifier(Modifier::BOLD))
+                } else {
+                    ("", Style::default().fg(Color::DarkGray))
+                };
+                spans.push(Span::raw(" | "));
+                spans.push(Span::styled(format!("{}flows evicted {}", label, app.flows.evicted()), style));
+            }
+            if app.conversations.evicted() > 0 {
+                let (label, style) = if app.conversations_evicting() {
+                    (" CONVERSATION TABLE FULL ", Style::default().fg(Color::White).bg(Color::Red).add_modifier(Modifier::BOLD))
+                } else {
+                    ("", Style::default().fg(Color::DarkGray))
+                };
+                spans.push(Span::raw(" | "));
+                spans.push(Span::styled(format!("{}conversations evicted {}", label, app.conversations.evicted()), style));
+            }
+
+            // Counters are kept in memory and retried, but nothing reaches the database until this clears
+            if let Some(err) = &app.history_error {
+                spans.push(Span::raw(" | "));
+                spans.push(Span::styled(
+                    format!(" HISTORY NOT SAVED: {} ", err),
+                    Style::default().fg(Color::White).bg(Color::Red).add_modifier(Modifier::BOLD),
+                ));
+            }
+
+            // The replayed file has been read to the end, or every capture thread stopped
+            if app.finished() {
+                let label = if app.offline { " DONE " } else { " CAPTURE STOPPED " };
+                spans.push(Span::raw(" | "));
+                spans.push(Span::styled(label, Style::default().fg(Color::Black).bg(Color::Green).add_modifier(Modifier::BOLD)));
+            }
+            for iface in app.interfaces.iter().filter(|iface| iface.error.is_some()) {
+                spans.push(Span::raw(" | "));
+                spans.push(Span::styled(
+                    format!(" READ ERROR {}: {} ", iface.name, iface.error.as_deref().unwrap_or_default()),
+                    Style::default().fg(Color::White).bg(Color::Red).add_modifier(Modifier::BOLD),
+                ));
+            }
+
+            spans.push(Span::raw(format!(
+                " | tick {}ms, window {}s | 'i' switch interface | 'f' flows | 'g' groups | 'v' VLANs | 'p' services | 'w' conversations | 'd' destinations | ↑/↓ select, 'Enter' details | 's'/'r' sort | '+'/'-' tick | '['/']' window | Press 'q' to quit",
+                app.timing.tick_rate.as_millis(),
+                app.timing.history_window.as_secs(),
+            )));
+            let status_content = Line::from(spans);
 
             let status_bar = Paragraph::new(status_content)
                 .style(Style::default().bg(Color::Rgb(20, 20, 20)));
-            f.render_widget(status_bar, main_chunks[2]);
+            f.render_widget(status_bar, main_chunks[3]);
         })?;
 
         // Handle input
+        let tick_rate = app.timing.tick_rate;
         let timeout = tick_rate.checked_sub(app.last_tick.elapsed()).unwrap_or_else(|| Duration::from_secs(0));
         if crossterm::event::poll(timeout)? {
             if let Event::Key(key) = event::read()? {
-                if key.code == KeyCode::Char('q') || key.code == KeyCode::Char('c') {
-                    return Ok(());
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Char('c') => return Ok(()),
+                    KeyCode::Char('i') | KeyCode::Tab => app.next_interface(),
+                    KeyCode::Char('f') => app.toggle_view(View::Flows),
+                    KeyCode::Char('g') => app.toggle_view(View::Groups),
+                    KeyCode::Char('v') => app.toggle_view(View::Segments),
+                    KeyCode::Char('p') => app.toggle_view(View::Services),
+                    KeyCode::Char('w') => app.toggle_view(View::Conversations),
+                    KeyCode::Char('d') => app.toggle_view(View::Destinations),
+                    KeyCode::Char('+') | KeyCode::Char('=') => app.step_tick_rate(true),
+                    KeyCode::Char('-') => app.step_tick_rate(false),
+                    KeyCode::Char(']') => app.step_history_window(true),
+                    KeyCode::Char('[') => app.step_history_window(false),
+                    KeyCode::Up | KeyCode::Char('k') => app.move_selection(-1),
+                    KeyCode::Down | KeyCode::Char('j') => app.move_selection(1),
+                    KeyCode::PageUp => app.move_selection(-page),
+                    KeyCode::PageDown => app.move_selection(page),
+                    KeyCode::Home => app.select_first(),
+                    KeyCode::End => app.select_last(),
+                    KeyCode::Enter => app.toggle_detail(),
+                    KeyCode::Esc if app.detail_host.is_some() => app.detail_host = None,
+                    KeyCode::Esc => app.selected_talker = None,
+                    KeyCode::Char('s') => app.cycle_sort(),
+                    KeyCode::Char('r') => app.reverse_sort(),
+                    _ => {}
                 }
             }
         }
-        if app.last_tick.elapsed() >= tick_rate {
+        if app.last_tick.elapsed() >= app.timing.tick_rate {
             app.on_tick(&stats);
-            app.last_tick = Instant::now();
         }
     }
 }
+
+// Download/Upload graphs with current, peak and total figures on the right
+fn draw_traffic_box(f: &mut Frame, area: Rect, title: &str, iface: &InterfaceStats) {
+    let net_block = Block::default()
+        .borders(Borders::ALL)
+        .title(title.to_string())
+        .border_type(ratatui::widgets::BorderType::Rounded)
+        .border_style(Style::default().fg(Color::Cyan));
+    f.render_widget(net_block.clone(), area);
+
+    let inner_area = net_block.inner(area);
+    let graph_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(75), Constraint::Percentage(25)].as_ref())
+        .split(inner_area);
+
+    // ======== Left Graphs (Download/Upload) ========
+    let chart_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+        .split(graph_chunks[0]);
+
+    draw_rate_graph(f, chart_chunks[0], " Download ", &iface.rx_history, Color::Red);
+    draw_rate_graph(f, chart_chunks[1], " Upload ", &iface.tx_history, Color::Blue);
+
+    // textual stats on the right
+    let text_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+        .split(graph_chunks[1]);
+
+    let current_rx_bps = iface.current_rx_rate();
+    let current_tx_bps = iface.current_tx_rate();
+    
+    let peak_rx_bps = iface.peak_rx_record.0;
+    let peak_tx_bps = iface.peak_tx_record.0;
+
+    let rx_text = vec![
+        Line::from(vec![Span::raw("▼ "), Span::styled(format_bps(current_rx_bps), Style::default().fg(Color::White).add_modifier(Modifier::BOLD))]),
+        Line::from(vec![Span::styled("  Peak: ", Style::default().fg(Color::DarkGray)), Span::raw(format_bps(peak_rx_bps))]),
+        Line::from(vec![Span::styled("  Tot:  ", Style::default().fg(Color::DarkGray)), Span::raw(format_bytes_total(iface.total_rx_bytes))]),
+    ];
+    f.render_widget(Paragraph::new(rx_text).block(Block::default().style(Style::default().fg(Color::Red))), text_chunks[0]);
+
+    let mut tx_text = vec![
+        Line::from(vec![Span::raw("▲ "), Span::styled(format_bps(current_tx_bps), Style::default().fg(Color::White).add_modifier(Modifier::BOLD))]),
+        Line::from(vec![Span::styled("  Peak: ", Style::default().fg(Color::DarkGray)), Span::raw(format_bps(peak_tx_bps))]),
+        Line::from(vec![Span::styled("  Tot:  ", Style::default().fg(Color::DarkGray)), Span::raw(format_bytes_total(iface.total_tx_bytes))]),
+    ];
+    // Only routers, bridges, arpspoof setups and mirror ports see traffic that is neither ours to send nor receive
+    if iface.total_fwd_bytes > 0 {
+        tx_text.push(Line::from(""));
+        tx_text.push(Line::from(vec![
+            Span::styled("⇄ Fwd: ", Style::default().fg(Color::Magenta)),
+            Span::raw(format_bps(iface.fwd_rate)),
+        ]));
+        tx_text.push(Line::from(vec![Span::styled("  Tot:  ", Style::default().fg(Color::DarkGray)), Span::raw(format_bytes_total(iface.total_fwd_bytes))]));
+    }
+    f.render_widget(Paragraph::new(tx_text).block(Block::default().style(Style::default().fg(Color::Blue))), text_chunks[1]);
+}
+
+// Bar per sample in bytes/s, oldest on the left
+fn draw_rate_graph(f: &mut Frame, area: Rect, title: &str, rates: &[f64], color: Color) {
+    let max = rates.iter().cloned().fold(100.0, f64::max);
+    let canvas = Canvas::default()
+        .block(Block::default().title(title.to_string()).title_style(Style::default().fg(color)))
+        .marker(Marker::Braille)
+        .x_bounds([0.0, rates.len() as f64])
+        .y_bounds([0.0, max])
+        .paint(|ctx| {
+            for (i, &val) in rates.iter().enumerate() {
+                ctx.draw(&CanvasLine {
+                    x1: i as f64,
+                    y1: 0.0,
+                    x2: i as f64,
+                    y2: val,
+                    color,
+                });
+            }
+        });
+    f.render_widget(canvas, area);
+}
+
+// One row per capture interface, the selected one highlighted
+fn draw_interface_breakdown(f: &mut Frame, area: Rect, app: &App) {
+    let header_cells = ["Interface", "RX Rate", "TX Rate", "Fwd Rate", "Peak RX", "Peak TX", "Total RX", "Total TX", "Dropped"]
+        .iter()
+        .map(|h| Cell::from(*h).style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)));
+    let header = Row::new(header_cells)
+        .style(Style::default().bg(Color::Rgb(40, 40, 40)))
+        .height(1);
+
+    let rows = app.interfaces.iter().enumerate().map(|(i, iface)| {
+        let style = if app.selected_interface == Some(i) {
+            Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        };
+        Row::new(vec![
+            Cell::from(iface.name.clone()),
+            Cell::from(format_bps(iface.current_rx_rate())).style(Style::default().fg(Color::Red)),
+            Cell::from(format_bps(iface.current_tx_rate())).style(Style::default().fg(Color::Blue)),
+            Cell::from(format_bps(iface.fwd_rate)).style(Style::default().fg(Color::Magenta)),
+            Cell::from(format_bps(iface.peak_rx_record.0)),
+            Cell::from(format_bps(iface.peak_tx_record.0)),
+            Cell::from(format_bytes_total(iface.total_rx_bytes)),
+            Cell::from(format_bytes_total(iface.total_tx_bytes)),
+            match iface.capture {
+                Some(capture) if iface.drops_rising() => Cell::from(capture.lost().to_string()).style(Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)),
+                Some(capture) => Cell::from(capture.lost().to_string()),
+                None => Cell::from("-"),
+            },
+        ]).style(style)
+    });
+
+    let table = Table::new(rows, [Constraint::Ratio(1, 9); 9])
+        .header(header)
+        .block(Block::default().title(" Interfaces ").borders(Borders::ALL).border_type(ratatui::widgets::BorderType::Rounded));
+    f.render_widget(table, area);
+}
+
+// Top talkers: per-host averages, peaks and totals
+fn draw_talkers(f: &mut Frame, area: Rect, app: &App, state: &mut TableState) {
+
+    // One averaging column per window, iftop-style, between the host identity and the window averages
+    let window_headers = app.timing.avg_windows.iter().map(|w| format_window(*w));
+    let header_cells = ["IP Address", "Name", "MAC", "Vendor"].into_iter().map(String::from)
+        .chain(window_headers)
+        .chain(["RX", "TX", "Peak Rate", "Peak Time", "Total RX", "Total TX"].into_iter().map(String::from))
+        .map(|h| Cell::from(h).style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)));
+    let header = Row::new(header_cells)
+        .style(Style::default().bg(Color::Rgb(40, 40, 40)))
+        .height(1)
+        .bottom_margin(0);
+
+    let rows = app.visible_talkers().iter().map(|talker| {
+        let peak_color = if talker.peak_rate > 1_000_000.0 { Color::Magenta } else { Color::Cyan };
+        // A label from the config file wins over a name learned from the wire
+        let name = match &talker.label {
+            Some(label) => Cell::from(label.clone()).style(Style::default().fg(Color::White).add_modifier(Modifier::BOLD)),
+            None => Cell::from(talker.name.clone().unwrap_or_default()).style(Style::default().fg(Color::Cyan)),
+        };
+
+        let mut cells = vec![
+            Cell::from(talker.ip.to_string()),
+            name,
+            Cell::from(talker.mac.map(|m| m.to_string()).unwrap_or_default()).style(Style::default().fg(Color::DarkGray)),
+            Cell::from(talker.vendor.unwrap_or("")),
+        ];
+        cells.extend(talker.window_rates.iter().map(|&bps| Cell::from(format_bps(bps)).style(Style::default().fg(rate_color(bps)))));
+        cells.extend([
+            Cell::from(format_bps(talker.avg_rx)).style(Style::default().fg(Color::Red)),
+            Cell::from(format_bps(talker.avg_tx)).style(Style::default().fg(Color::Blue)),
+            Cell::from(format_bps(talker.peak_rate)).style(Style::default().fg(peak_color)),
+            Cell::from(talker.peak_time.format("%H:%M:%S").to_string()).style(Style::default().fg(Color::DarkGray)),
+            Cell::from(format_bytes_total(talker.total_rx_bytes)),
+            Cell::from(format_bytes_total(talker.total_tx_bytes)),
+        ]);
+        Row::new(cells).height(1)
+    });
+
+    // The averaging columns share a fixed slice of the width however many windows there are
+    let windows = app.timing.avg_windows.len().max(1) as u16;
+    let widths: Vec<Constraint> = [15, 12, 10, 9].into_iter().map(Constraint::Percentage)
+        .chain((0..app.timing.avg_windows.len()).map(|_| Constraint::Percentage(18 / windows)))
+        .chain([6, 6, 6, 6, 6, 6].into_iter().map(Constraint::Percentage))
+        .collect();
+
+    let title = format!(
+        " Local Network Traffic (RX/TX averaged over {}, {} hosts, sorted by {}{}) ",
+        format_window(app.timing.history_window),
+        app.visible_talkers().len(),
+        app.sort_column.label(),
+        if app.sort_reversed { ", reversed" } else { "" },
+    );
+    let table = Table::new(rows, widths)
+        .header(header)
+        .highlight_style(Style::default().bg(Color::Rgb(60, 60, 90)).add_modifier(Modifier::BOLD))
+        .highlight_symbol("▶ ")
+        .block(Block::default()
+            .title(title)
+            .borders(Borders::ALL)
+            .border_type(ratatui::widgets::BorderType::Rounded));
+    state.select(app.selected_index());
+    f.render_stateful_widget(table, area, state);
+}
+
+// Talkers added up per config group
+fn draw_groups(f: &mut Frame, area: Rect, app: &App) {
+
+    let header_cells = ["Group", "Hosts"].into_iter().map(String::from)
+        .chain(app.timing.avg_windows.iter().map(|w| format_window(*w)))
+        .chain(["RX", "TX", "Total RX", "Total TX"].into_iter().map(String::from))
+        .map(|h| Cell::from(h).style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)));
+    let header = Row::new(header_cells)
+        .style(Style::default().bg(Color::Rgb(40, 40, 40)))
+        .height(1);
+
+    let rows = app.group_totals().into_iter().map(|group| {
+        let mut cells = vec![
+            Cell::from(group.name).style(Style::default().add_modifier(Modifier::BOLD)),
+            Cell::from(group.hosts.to_string()),
+        ];
+        cells.extend(group.window_rates.iter().map(|&bps| Cell::from(format_bps(bps)).style(Style::default().fg(rate_color(bps)))));
+        cells.extend([
+            Cell::from(format_bps(group.avg_rx)).style(Style::default().fg(Color::Red)),
+            Cell::from(format_bps(group.avg_tx)).style(Style::default().fg(Color::Blue)),
+            Cell::from(format_bytes_total(group.total_rx_bytes)),
+            Cell::from(format_bytes_total(group.total_tx_bytes)),
+        ]);
+        Row::new(cells)
+    });
+
+    let columns = 6 + app.timing.avg_windows.len() as u32;
+    let table = Table::new(rows, vec![Constraint::Ratio(1, columns); columns as usize])
+        .header(header)
+        .block(Block::default()
+            .title(format!(" Groups (RX/TX averaged over {}) ", format_window(app.timing.history_window)))
+            .borders(Borders::ALL)
+            .border_type(ratatui::widgets::BorderType::Rounded));
+    f.render_widget(table, area);
+}
+
+// Green below 100 KB/s, yellow below 1 MB/s, red above
+fn rate_color(rate: f64) -> Color {
+    if rate > 1_000_000.0 {
+        Color::Red
+    } else if rate > 100_000.0 {
+        Color::LightYellow
+    } else {
+        Color::Green
+    }
+}
+
+// "2s", "10s", "5m"
+fn format_window(window: Duration) -> String {
+    let secs = window.as_secs();
+    if secs >= 60 && secs.is_multiple_of(60) { format!("{}m", secs / 60) } else { format!("{}s", secs) }
+}
+
+// Heaviest 5-tuple flows
+fn draw_flows(f: &mut Frame, area: Rect, app: &App) {
+    let header_cells = ["Proto", "Source", "Destination", "Rate", "Bytes", "Packets", "First Seen", "Last Seen"]
+        .iter()
+        .map(|h| Cell::from(*h).style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)));
+    let header = Row::new(header_cells)
+        .style(Style::default().bg(Color::Rgb(40, 40, 40)))
+        .height(1);
+
+    let format_time = |t: SystemTime| DateTime::<Local>::from(t).format("%H:%M:%S").to_string();
+    let visible = area.height.saturating_sub(3) as usize;
+    let rows = app.flows.top(visible).into_iter().map(|(key, stats)| {
+        Row::new(vec![
+            Cell::from(protocol_name(key.protocol)),
+            Cell::from(key.src_endpoint()),
+            Cell::from(key.dst_endpoint()),
+            Cell::from(format_bps(stats.rate)).style(Style::default().fg(rate_color(stats.rate))),
+            Cell::from(format_bytes_total(stats.bytes)),
+            Cell::from(stats.packets.to_string()),
+            Cell::from(format_time(stats.first_seen)).style(Style::default().fg(Color::DarkGray)),
+            Cell::from(format_time(stats.last_seen)).style(Style::default().fg(Color::DarkGray)),
+        ])
+    });
+
+    let title = match app.flows.evicted() {
+        0 => format!(" Flows ({} active) ", app.flows.len()),
+        evicted => format!(" Flows ({} active, {} evicted from the full table) ", app.flows.len(), evicted),
+    };
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Percentage(6),
+            Constraint::Percentage(24),
+            Constraint::Percentage(24),
+            Constraint::Percentage(10),
+            Constraint::Percentage(10),
+            Constraint::Percentage(8),
+            Constraint::Percentage(9),
+            Constraint::Percentage(9),
+        ]
+    )
+    .header(header)
+    .block(Block::default().title(title).borders(Borders::ALL).border_type(ratatui::widgets::BorderType::Rounded));
+    f.render_widget(table, area);
+}
+
+// Traffic per VLAN and per VXLAN/GENEVE network
+fn draw_segments(f: &mut Frame, area: Rect, app: &App) {
+    let header_cells = ["Segment", "Hosts", "Rate", "Bytes", "Packets", "Last Seen"]
+        .iter()
+        .map(|h| Cell::from(*h).style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)));
+    let header = Row::new(header_cells)
+        .style(Style::default().bg(Color::Rgb(40, 40, 40)))
+        .height(1);
+
+    let visible = area.height.saturating_sub(3) as usize;
+    let rows = app.segments.top(visible).into_iter().map(|(segment, stats)| {
+        let last_seen = stats.last_seen.map(|t| DateTime::<Local>::from(t).format("%H:%M:%S").to_string()).unwrap_or_default();
+        Row::new(vec![
+            Cell::from(segment.to_string()).style(Style::default().add_modifier(Modifier::BOLD)),
+            Cell::from(stats.hosts.len().to_string()),
+            Cell::from(format_bps(stats.rate)).style(Style::default().fg(rate_color(stats.rate))),
+            Cell::from(format_bytes_total(stats.bytes)),
+            Cell::from(stats.packets.to_string()),
+            Cell::from(last_seen).style(Style::default().fg(Color::DarkGray)),
+        ])
+    });
+
+    let title = if app.segments.len() == 0 {
+        " VLANs / Overlay Networks (no tagged or tunnelled traffic seen) ".to_string()
+    } else {
+        format!(" VLANs / Overlay Networks ({}) ", app.segments.len())
+    };
+    let table = Table::new(rows, [Constraint::Ratio(1, 6); 6])
+        .header(header)
+        .block(Block::default().title(title).borders(Borders::ALL).border_type(ratatui::widgets::BorderType::Rounded));
+    f.render_widget(table, area);
+}
+
+// Everything known about one host: identity, its own RX/TX graph, and its peers, ports and protocols
+fn draw_host_detail(f: &mut Frame, area: Rect, app: &App, ip: IpAddr) {
+    let history = app.host(&ip);
+    let mac = history.and_then(|h| h.mac);
+    let label = app.labels.label(&ip, mac);
+    let title = match label {
+        Some(label) => format!(" Host {} ({}) ", ip, label),
+        None => format!(" Host {} ", ip),
+    };
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(title)
+        .border_type(ratatui::widgets::BorderType::Rounded)
+        .border_style(Style::default().fg(Color::Cyan));
+    f.render_widget(block.clone(), area);
+    let Some(history) = history else {
+        f.render_widget(Paragraph::new(" No traffic from this host any more."), block.inner(area));
+        return;
+    };
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(16), Constraint::Min(5)].as_ref())
+        .split(block.inner(area));
+    let top = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(35), Constraint::Percentage(65)].as_ref())
+        .split(chunks[0]);
+
+    // ======== Identity, rates and totals ========
+    let key = |text: &str| Span::styled(format!("{:<12}", text), Style::default().fg(Color::DarkGray));
+    let format_time = |t: Option<SystemTime>| t.map(|t| DateTime::<Local>::from(t).format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_else(|| "-".to_string());
+    let (rx_rate, tx_rate) = history.current_rates();
+    let (avg_rx, avg_tx) = history.average_over(app.timing.history_window);
+    let mut lines = vec![
+        Line::from(vec![key("MAC"), Span::raw(mac.map(|m| m.to_string()).unwrap_or_else(|| "-".to_string()))]),
+        Line::from(vec![key("Vendor"), Span::raw(history.vendor.unwrap_or("-"))]),
+        Line::from(vec![key("Group"), Span::raw(app.labels.group(&ip, mac).unwrap_or("-"))]),
+        Line::from(vec![key("Interface"), Span::raw(app.interfaces.get(history.interface).map_or("-", |i| i.name.as_str()))]),
+        Line::from(vec![key("First seen"), Span::raw(format_time(history.first_seen))]),
+        Line::from(vec![key("Last seen"), Span::raw(format_time(history.last_seen))]),
+        Line::from(vec![key("Now"), Span::styled(format!("▼ {}  ▲ {}", format_bps(rx_rate), format_bps(tx_rate)), Style::default().add_modifier(Modifier::BOLD))]),
+        Line::from(vec![key(&format!("Avg {}", format_window(app.timing.history_window))), Span::raw(format!("▼ {}  ▲ {}", format_bps(avg_rx), format_bps(avg_tx)))]),
+        Line::from(vec![key("Peak"), Span::raw(format!("▼ {}  ▲ {}", format_bps(history.peak_rx_rate), format_bps(history.peak_tx_rate)))]),
+        Line::from(vec![key("Total"), Span::raw(format!("▼ {}  ▲ {}", format_bytes_total(history.total_rx_bytes), format_bytes_total(history.total_tx_bytes)))]),
+    ];
+    // Names learned from the wire, most trustworthy first
+    if let Some(names) = app.hostnames.get(&ip) {
+        for (source, name) in names.iter() {
+            lines.push(Line::from(vec![key(source.label()), Span::styled(name.to_string(), Style::default().fg(Color::Cyan))]));
+        }
+    }
+    f.render_widget(Paragraph::new(lines), top[0]);
+
+    // ======== The host's own RX/TX graph from its samples ========
+    let graphs = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+        .split(top[1]);
+    let rate = |bytes: u64, duration: Duration| if duration.is_zero() { 0.0 } else { bytes as f64 / duration.as_secs_f64() };
+    let rx: Vec<f64> = history.samples.iter().map(|s| rate(s.rx, s.duration)).collect();
+    let tx: Vec<f64> = history.samples.iter().map(|s| rate(s.tx, s.duration)).collect();
+    draw_rate_graph(f, graphs[0], " Received ", &rx, Color::Red);
+    draw_rate_graph(f, graphs[1], " Sent ", &tx, Color::Blue);
+
+    // ======== Peers from the active flows, services and protocols over the host's lifetime ========
+    let tables = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(40), Constraint::Percentage(60)].as_ref())
+        .split(chunks[1]);
+    let rows = tables[0].height.saturating_sub(3) as usize;
+    let peers = app.flows.peers(ip).into_iter().take(rows).map(|(peer, bytes)| {
+        let name = host_name(app, &peer).unwrap_or("");
+        let place = app.geo.lookup(peer).map(|info| info.label()).unwrap_or_default();
+        Row::new(vec![
+            Cell::from(peer.to_string()),
+            Cell::from(name.to_string()).style(Style::default().fg(Color::Cyan)),
+            Cell::from(place).style(Style::default().fg(Color::Magenta)),
+            Cell::from(format_bytes_total(bytes)),
+        ])
+    });
+    let header = Row::new(["Peer", "Name", "Where", "Bytes"].map(|h| Cell::from(h).style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))))
+        .style(Style::default().bg(Color::Rgb(40, 40, 40)));
+    f.render_widget(
+        Table::new(peers, [Constraint::Percentage(35), Constraint::Percentage(20), Constraint::Percentage(27), Constraint::Percentage(18)])
+            .header(header)
+            .block(Block::default().title(" Top Peers ").borders(Borders::ALL).border_type(ratatui::widgets::BorderType::Rounded)),
+        tables[0],
+    );
+    draw_service_tables(f, tables[1], &history.services);
+}
+
+// Config label, else the best name learned from the wire
+fn host_name<'a>(app: &'a App, ip: &IpAddr) -> Option<&'a str> {
+    app.labels.label(ip, app.host(ip).and_then(|h| h.mac))
+        .or_else(|| app.hostnames.get(ip).and_then(|names| names.best()))
+}
+
+// Host pairs, busiest first, with the bytes sent each way
+fn draw_conversations(f: &mut Frame, area: Rect, app: &App) {
+    let header_cells = ["Host", "Name", "Peer", "Name", "Scope", "Rate", "Host → Peer", "Peer → Host", "Last Seen"]
+        .iter()
+        .map(|h| Cell::from(*h).style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)));
+    let header = Row::new(header_cells)
+        .style(Style::default().bg(Color::Rgb(40, 40, 40)))
+        .height(1);
+
+    let visible = area.height.saturating_sub(3) as usize;
+    let rows = app.conversations.top(visible).into_iter().map(|(key, conversation)| {
+        let (scope, scope_color) = if conversation.local { ("LAN", Color::Cyan) } else { ("External", Color::Magenta) };
+        Row::new(vec![
+            Cell::from(key.a.to_string()).style(Style::default().add_modifier(Modifier::BOLD)),
+            Cell::from(host_name(app, &key.a).unwrap_or("").to_string()).style(Style::default().fg(Color::Cyan)),
+            Cell::from(key.b.to_string()),
+            Cell::from(host_name(app, &key.b).unwrap_or("").to_string()).style(Style::default().fg(Color::Cyan)),
+            Cell::from(scope).style(Style::default().fg(scope_color)),
+            Cell::from(format_bps(conversation.rate)).style(Style::default().fg(rate_color(conversation.rate))),
+            Cell::from(format_bytes_total(conversation.a_to_b)),
+            Cell::from(format_bytes_total(conversation.b_to_a)),
+            Cell::from(DateTime::<Local>::from(conversation.last_seen).format("%H:%M:%S").to_string()).style(Style::default().fg(Color::DarkGray)),
+        ])
+    });
+
+    let title = match app.conversations.evicted() {
+        0 => format!(" Conversations ({} active) ", app.conversations.len()),
+        evicted => format!(" Conversations ({} active, {} evicted from the full table) ", app.conversations.len(), evicted),
+    };
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Percentage(15),
+            Constraint::Percentage(12),
+            Constraint::Percentage(15),
+            Constraint::Percentage(12),
+            Constraint::Percentage(8),
+            Constraint::Percentage(10),
+            Constraint::Percentage(10),
+            Constraint::Percentage(10),
+            Constraint::Percentage(8),
+        ]
+    )
+    .header(header)
+    .block(Block::default().title(title).borders(Borders::ALL).border_type(ratatui::widgets::BorderType::Rounded));
+    f.render_widget(table, area);
+}
+
+// Where the bandwidth goes outside the tracked networks: per autonomous system and per remote address
+fn draw_destinations(f: &mut Frame, area: Rect, app: &App) {
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(45), Constraint::Percentage(55)].as_ref())
+        .split(area);
+    let header = |cells: &[&'static str]| Row::new(cells.iter().map(|h| Cell::from(*h).style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))))
+        .style(Style::default().bg(Color::Rgb(40, 40, 40)));
+
+    let visible = chunks[0].height.saturating_sub(3) as usize;
+    let asns = app.asns.top(visible).into_iter().map(|(asn, stats)| {
+        let asn = if asn == 0 { "-".to_string() } else { format!("AS{}", asn) };
+        Row::new(vec![
+            Cell::from(asn).style(Style::default().add_modifier(Modifier::BOLD)),
+            Cell::from(stats.org.clone().unwrap_or_else(|| "Unknown".to_string())),
+            Cell::from(stats.country.clone().unwrap_or_default()),
+            Cell::from(stats.hosts.len().to_string()),
+            Cell::from(format_bps(stats.rate)).style(Style::default().fg(rate_color(stats.rate))),
+            Cell::from(format_bytes_total(stats.sent)),
+            Cell::from(format_bytes_total(stats.received)),
+        ])
+    });
+    let title = if app.geo.is_empty() {
+        " Autonomous Systems (no database, see --geoip) ".to_string()
+    } else {
+        format!(" Autonomous Systems ({}) ", app.asns.len())
+    };
+    f.render_widget(
+        Table::new(asns, [
+            Constraint::Percentage(13),
+            Constraint::Percentage(30),
+            Constraint::Percentage(8),
+            Constraint::Percentage(8),
+            Constraint::Percentage(15),
+            Constraint::Percentage(13),
+            Constraint::Percentage(13),
+        ])
+        .header(header(&["ASN", "Organization", "CC", "Hosts", "Rate", "Sent", "Received"]))
+        .block(Block::default().title(title).borders(Borders::ALL).border_type(ratatui::widgets::BorderType::Rounded)),
+        chunks[0],
+    );
+
+    let visible = chunks[1].height.saturating_sub(3) as usize;
+    let rows = app.conversations.remotes(None, visible).into_iter().map(|(key, conversation)| {
+        let place = conversation.remote.as_ref().map(|info| info.label()).unwrap_or_default();
+        Row::new(vec![
+            Cell::from(host_name(app, &key.a).map_or_else(|| key.a.to_string(), str::to_string)).style(Style::default().add_modifier(Modifier::BOLD)),
+            Cell::from(key.b.to_string()),
+            Cell::from(place).style(Style::default().fg(Color::Magenta)),
+            Cell::from(format_bps(conversation.rate)).style(Style::default().fg(rate_color(conversation.rate))),
+            Cell::from(format_bytes_total(conversation.a_to_b)),
+            Cell::from(format_bytes_total(conversation.b_to_a)),
+        ])
+    });
+    f.render_widget(
+        Table::new(rows, [
+            Constraint::Percentage(18),
+            Constraint::Percentage(22),
+            Constraint::Percentage(27),
+            Constraint::Percentage(11),
+            Constraint::Percentage(11),
+            Constraint::Percentage(11),
+        ])
+        .header(header(&["Host", "Remote", "Where", "Rate", "Sent", "Received"]))
+        .block(Block::default().title(" Top Remote Destinations ").borders(Borders::ALL).border_type(ratatui::widgets::BorderType::Rounded)),
+        chunks[1],
+    );
+}
+
+// Traffic split by IP protocol and by well-known service port
+fn draw_services(f: &mut Frame, area: Rect, app: &App) {
+    draw_service_tables(f, area, &app.services);
+}
+
+// Protocol split on the left, services on the right, with each one's share of the bytes
+fn draw_service_tables(f: &mut Frame, area: Rect, services: &ServiceTable) {
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(35), Constraint::Percentage(65)].as_ref())
+        .split(area);
+    let header = |cells: &[&'static str]| Row::new(cells.iter().map(|h| Cell::from(*h).style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))))
+        .style(Style::default().bg(Color::Rgb(40, 40, 40)));
+    let total = services.total_bytes().max(1) as f64;
+    let share = |bytes: u64| format!("{:.1}%", bytes as f64 * 100.0 / total);
+
+    let classes = services.classes().into_iter().map(|(class, stats)| {
+        Row::new(vec![
+            Cell::from(class).style(Style::default().add_modifier(Modifier::BOLD)),
+            Cell::from(format_bps(stats.rate)).style(Style::default().fg(rate_color(stats.rate))),
+            Cell::from(format_bytes_total(stats.bytes)),
+            Cell::from(share(stats.bytes)),
+        ])
+    });
+    f.render_widget(
+        Table::new(classes, [Constraint::Ratio(1, 4); 4])
+            .header(header(&["Protocol", "Rate", "Bytes", "Share"]))
+            .block(Block::default().title(" Protocols ").borders(Borders::ALL).border_type(ratatui::widgets::BorderType::Rounded)),
+        chunks[0],
+    );
+
+    let visible = chunks[1].height.saturating_sub(3) as usize;
+    let rows = services.top(visible).into_iter().map(|(service, stats)| {
+        let port = if service.port == 0 { String::new() } else { service.port.to_string() };
+        Row::new(vec![
+            Cell::from(service.name()).style(Style::default().add_modifier(Modifier::BOLD)),
+            Cell::from(protocol_name(service.protocol)),
+            Cell::from(port),
+            Cell::from(format_bps(stats.rate)).style(Style::default().fg(rate_color(stats.rate))),
+            Cell::from(format_bytes_total(stats.bytes)),
+            Cell::from(stats.packets.to_string()),
+            Cell::from(share(stats.bytes)),
+        ])
+    });
+    f.render_widget(
+        Table::new(rows, [
+            Constraint::Percentage(22),
+            Constraint::Percentage(10),
+            Constraint::Percentage(10),
+            Constraint::Percentage(16),
+            Constraint::Percentage(16),
+            Constraint::Percentage(14),
+            Constraint::Percentage(12),
+        ])
+            .header(header(&["Service", "Proto", "Port", "Rate", "Bytes", "Packets", "Share"]))
+            .block(Block::default().title(" Services ").borders(Borders::ALL).border_type(ratatui::widgets::BorderType::Rounded)),
+        chunks[1],
+    );
+}
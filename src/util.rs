@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 // Format function: Convert Bytes/s to bits/s for display
 pub fn format_bps(bytes_per_sec: f64) -> String {
     let bps = bytes_per_sec * 8.0; //convert to bits per second
@@ -30,3 +32,21 @@ pub fn format_bytes_total(bytes: u64) -> String {
         format!("{} B", bytes)
     }
 }
+
+// Parse a period such as "90s", "30m", "24h", "7d" or "2w"
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let value: u64 = number.parse().map_err(|_| format!("invalid duration '{}'", s))?;
+    let secs = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 24 * 3600,
+        "w" => 7 * 24 * 3600,
+        _ => return Err(format!("invalid duration unit in '{}', expected s, m, h, d or w", s)),
+    };
+    let secs = value.checked_mul(secs).ok_or_else(|| format!("duration '{}' is too large", s))?;
+    Ok(Duration::from_secs(secs))
+}